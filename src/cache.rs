@@ -0,0 +1,222 @@
+use crate::events::{Events, StateEvent};
+use crate::key::{StateId, StateKey};
+use crate::state::{panic_message, State};
+use crate::stats::Histogram;
+
+use std::collections::{HashMap, VecDeque};
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+/// capacity of the cache that keeps the released states for reuse
+///
+/// the capacity counts all the live states of a `StateLock`, including the one that is
+/// going to be tear up. before a new state is tear up, the least recently used states
+/// are tear down to make room for it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CacheCapacity {
+    /// at most N states are alive, `Lru(1)` is the default
+    /// which means the last state is always tear down before a new state is tear up
+    Lru(usize),
+    /// the total `State::size_hint` of the live states is at most the weight.
+    /// the size hint of a state that is never seen is assumed to be 1
+    Weighted(usize),
+}
+
+impl Default for CacheCapacity {
+    fn default() -> Self {
+        CacheCapacity::Lru(1)
+    }
+}
+
+struct CachedState {
+    state: Box<dyn State>,
+    // the key of a keyed state
+    key: Option<StateKey>,
+    weight: usize,
+    // the time the state is released
+    parked_at: Instant,
+}
+
+/// released states that could be reused, least recently used first
+pub(crate) struct StateCache {
+    capacity: CacheCapacity,
+    states: VecDeque<CachedState>,
+    // total weight of the cached states
+    weight: usize,
+    // the last known weight of each state
+    weights: HashMap<&'static str, usize>,
+    // the time to tear down each state
+    pub(crate) tear_down_time: HashMap<&'static str, Histogram>,
+    events: Arc<Events>,
+}
+
+impl StateCache {
+    pub(crate) fn new(capacity: CacheCapacity, events: Arc<Events>) -> Self {
+        StateCache {
+            capacity,
+            states: VecDeque::new(),
+            weight: 0,
+            weights: HashMap::new(),
+            tear_down_time: HashMap::new(),
+            events,
+        }
+    }
+
+    fn max_weight(&self) -> usize {
+        match self.capacity {
+            CacheCapacity::Lru(n) | CacheCapacity::Weighted(n) => n,
+        }
+    }
+
+    fn weight_of(&self, state: &dyn State) -> usize {
+        match self.capacity {
+            CacheCapacity::Lru(_) => 1,
+            CacheCapacity::Weighted(_) => state.size_hint(),
+        }
+    }
+
+    /// names of the cached states, least recently used first
+    pub(crate) fn names(&self) -> impl Iterator<Item = &'static str> + '_ {
+        self.states.iter().map(|s| s.state.name())
+    }
+
+    /// take the cached state out for reuse
+    pub(crate) fn take(&mut self, id: &StateId) -> Option<Box<dyn State>> {
+        let pos = self
+            .states
+            .iter()
+            .position(|s| id.is(s.state.name(), s.key.as_ref()))?;
+        let cached = self.states.remove(pos)?;
+        self.weight -= cached.weight;
+        trace!("{id} state is reused from cache");
+        Some(cached.state)
+    }
+
+    /// convert a cached state into the next state in place, most recently used first
+    /// a state that panicked in the transition is tear down, the others are still tried
+    pub(crate) fn transition(&mut self, next: &str) -> Option<Box<dyn State>> {
+        for pos in (0..self.states.len()).rev() {
+            let cached_state = &mut self.states[pos].state;
+            let transition = AssertUnwindSafe(|| cached_state.transition_into(next));
+            let state = match panic::catch_unwind(transition) {
+                Ok(Some(state)) => state,
+                Ok(None) => continue,
+                Err(e) => {
+                    let cached = self.states.remove(pos).expect("no cached state");
+                    self.weight -= cached.weight;
+                    warn!(
+                        "{} state transition into {next} state panicked: {}",
+                        cached.state.name(),
+                        panic_message(&*e)
+                    );
+                    self.tear_down(cached.state);
+                    continue;
+                }
+            };
+            let cached = self.states.remove(pos).expect("no cached state");
+            self.weight -= cached.weight;
+            let old_state = cached.state.name();
+            // the resources are moved into the new state, no tear down
+            drop(cached);
+            if state.name() == next {
+                trace!("{old_state} state transition into {next} state");
+                return Some(state);
+            }
+            warn!(
+                "{old_state} state transition into {} state, expect {next}",
+                state.name()
+            );
+            self.tear_down(state);
+        }
+        None
+    }
+
+    /// cache the released state, evict the least recently used states if over capacity
+    /// a state whose size hint panicked is tear down instead
+    pub(crate) fn put(&mut self, state: Box<dyn State>, key: Option<StateKey>) {
+        let weight = panic::catch_unwind(AssertUnwindSafe(|| self.weight_of(state.as_ref())));
+        let weight = match weight {
+            Ok(weight) => weight,
+            Err(e) => {
+                let name = state.name();
+                warn!("{name} state size hint panicked: {}", panic_message(&*e));
+                return self.tear_down(state);
+            }
+        };
+        self.weights.insert(state.name(), weight);
+        self.weight += weight;
+        self.states.push_back(CachedState {
+            state,
+            key,
+            weight,
+            parked_at: Instant::now(),
+        });
+        while self.weight > self.max_weight() && self.evict_one() {}
+    }
+
+    /// evict the least recently used states to make room for the state to be tear up
+    pub(crate) fn make_room(&mut self, name: &str) {
+        let weight = match self.capacity {
+            CacheCapacity::Lru(_) => 1,
+            CacheCapacity::Weighted(_) => self.weights.get(name).copied().unwrap_or(1),
+        };
+        while self.weight + weight > self.max_weight() && self.evict_one() {}
+    }
+
+    /// tear down all the cached states
+    pub(crate) fn clear(&mut self) {
+        while self.evict_one() {}
+    }
+
+    /// tear down the states that are idle for `idle_timeout`
+    /// return the time that the next cached state would expire
+    pub(crate) fn evict_expired(&mut self, idle_timeout: Duration) -> Option<Instant> {
+        let now = Instant::now();
+        loop {
+            // the least recently used state is the one released earliest
+            let expire_at = self.states.front()?.parked_at + idle_timeout;
+            if expire_at > now {
+                return Some(expire_at);
+            }
+            self.evict_one();
+        }
+    }
+
+    /// tear down the least recently used state, return false if the cache is empty
+    fn evict_one(&mut self) -> bool {
+        match self.states.pop_front() {
+            Some(CachedState { state, weight, .. }) => {
+                self.weight -= weight;
+                self.tear_down(state);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// tear down the state, a panic in the tear down is logged and the state is discarded
+    pub(crate) fn tear_down(&mut self, mut state: Box<dyn State>) {
+        let start = Instant::now();
+        let old_state = state.name();
+        let tear_down = move || {
+            let tear_down = AssertUnwindSafe(move || {
+                state.tear_down();
+                // we should drop the old state completely before setup the new state
+                drop(state);
+            });
+            if let Err(e) = panic::catch_unwind(tear_down) {
+                warn!(
+                    "{old_state} state tear down panicked: {}",
+                    panic_message(&*e)
+                );
+            }
+        };
+        in_span!(tear_down, "state_lock.tear_down", state = old_state);
+        trace!("{old_state} state is dropped");
+        let histogram = self.tear_down_time.entry(old_state).or_default();
+        histogram.record(start.elapsed());
+        let event = StateEvent::TearDownFinished { state: old_state };
+        self.events.emit(event);
+    }
+}
@@ -0,0 +1,41 @@
+use may::sync::mpsc::{channel, Receiver, Sender};
+use may::sync::Mutex;
+
+/// events of the state changes, emitted to the subscribers of a `StateLock`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StateEvent {
+    /// the state is going to be tear up, no cached state could be reused
+    TearUpStarted { state: &'static str },
+    /// the state is active, could be a new state or a reused one
+    Activated { state: &'static str },
+    /// all the guards of the state are dropped
+    Released { state: &'static str },
+    /// the released state is tear down and dropped
+    TearDownFinished { state: &'static str },
+    /// a waiter is queued for the state
+    WaiterQueued { state: &'static str },
+}
+
+/// the subscribers of the state events, shared by the state lock and its state cache
+#[derive(Default)]
+pub(crate) struct Events {
+    subscribers: Mutex<Vec<Sender<StateEvent>>>,
+}
+
+impl Events {
+    pub(crate) fn subscribe(&self) -> Receiver<StateEvent> {
+        let (tx, rx) = channel();
+        self.subscribers.lock().unwrap().push(tx);
+        rx
+    }
+
+    /// send the event to all the subscribers, the dropped ones are removed
+    pub(crate) fn emit(&self, event: StateEvent) {
+        let mut subscribers = self.subscribers.lock().unwrap();
+        if subscribers.is_empty() {
+            return;
+        }
+        trace!("emit state event {event:?}");
+        subscribers.retain(|tx| tx.send(event).is_ok());
+    }
+}
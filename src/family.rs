@@ -0,0 +1,113 @@
+use crate::state::State;
+
+/// a state family that is known at compile time
+///
+/// a `StateLock<F>` only locks the states that are `MemberOf<F>` by type,
+/// the family marker types are generated by `#[derive(State)]`, use `family!` to name them.
+pub trait Family: 'static {
+    /// the family name that the states are registered with
+    fn family_name() -> &'static str;
+}
+
+/// the state belongs to the family `F`, implemented by `#[derive(State)]`
+pub trait MemberOf<F: Family>: State {}
+
+/// the family of the state lock is only known by name at runtime, this is the default
+///
+/// the state lock accepts any state type, a state from other families fails at runtime
+#[derive(Debug)]
+pub struct AnyFamily;
+
+/// the family marker of the family name, `ID` is the `family_id` of the name
+#[derive(Debug)]
+pub struct FamilyMarker<const ID: u64>;
+
+impl<const ID: u64> Family for FamilyMarker<ID> {
+    fn family_name() -> &'static str {
+        crate::registry::family_name(ID).expect("state family not found")
+    }
+}
+
+/// hash the family name into the family id, it's FNV-1a
+pub const fn family_id(name: &str) -> u64 {
+    let bytes = name.as_bytes();
+    let mut hash = 0xcbf2_9ce4_8422_2325_u64;
+    let mut i = 0;
+    while i < bytes.len() {
+        hash ^= bytes[i] as u64;
+        hash = hash.wrapping_mul(0x0100_0000_01b3);
+        i += 1;
+    }
+    hash
+}
+
+/// the family marker type of the family name
+///
+/// ```
+/// use state_lock::{family, State, StateLock};
+///
+/// const MODEL_FAMILY: &str = "Models";
+///
+/// #[derive(State, Default)]
+/// #[family(MODEL_FAMILY)]
+/// struct Llama;
+///
+/// type Models = family!(MODEL_FAMILY);
+///
+/// fn main() {
+///     let state_lock = StateLock::<Models>::typed();
+///     let llama = state_lock.lock::<Llama>().unwrap();
+///     assert_eq!(llama.name(), "Llama");
+/// }
+/// ```
+///
+/// a state of other families could not be locked by type
+///
+/// ```compile_fail
+/// use state_lock::{family, State, StateLock};
+///
+/// const MODEL_FAMILY: &str = "Models";
+/// const TOOL_FAMILY: &str = "Tools";
+///
+/// #[derive(State, Default)]
+/// #[family(MODEL_FAMILY)]
+/// struct Llama;
+///
+/// #[derive(State, Default)]
+/// #[family(TOOL_FAMILY)]
+/// struct Hammer;
+///
+/// fn main() {
+///     let state_lock = StateLock::<family!(MODEL_FAMILY)>::typed();
+///     let _hammer = state_lock.lock::<Hammer>();
+/// }
+/// ```
+///
+/// the guards of the typed state lock could only switch to the states of the family
+///
+/// ```compile_fail
+/// use state_lock::{family, State, StateLock};
+///
+/// const MODEL_FAMILY: &str = "Models";
+/// const TOOL_FAMILY: &str = "Tools";
+///
+/// #[derive(State, Default)]
+/// #[family(MODEL_FAMILY)]
+/// struct Llama;
+///
+/// #[derive(State, Default)]
+/// #[family(TOOL_FAMILY)]
+/// struct Hammer;
+///
+/// fn main() {
+///     let state_lock = StateLock::<family!(MODEL_FAMILY)>::typed();
+///     let llama = state_lock.lock::<Llama>().unwrap();
+///     let _hammer = llama.switch_to::<Hammer>();
+/// }
+/// ```
+#[macro_export]
+macro_rules! family {
+    ($family:expr) => {
+        $crate::FamilyMarker<{ $crate::family_id($family) }>
+    };
+}
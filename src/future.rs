@@ -0,0 +1,85 @@
+use crate::key::StateId;
+use crate::state::RawState;
+use crate::waiter::{AsyncWaiter, Waiter};
+use crate::{AnyFamily, StateLock};
+
+use std::fmt::{self, Debug};
+use std::future::Future;
+use std::io;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+
+/// future that resolves to the locked state, works on any async executor
+///
+/// the future is registered in the waiter queue on the first poll,
+/// dropping it before it's ready would remove it from the queue.
+///
+/// if the state could be activated without waiting, the first poll tears up
+/// the state in place, which blocks the executor thread until tear up is done.
+/// the states torn up for the waiting futures are done by the releasing side.
+pub struct LockFuture<'a, F = AnyFamily> {
+    state_lock: &'a StateLock<F>,
+    state_id: StateId,
+    // the registered waiter, `None` if not registered yet
+    waiter: Option<Arc<AsyncWaiter>>,
+}
+
+impl<F> Debug for LockFuture<'_, F> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("LockFuture")
+            .field("state_family", &self.state_lock.state_family())
+            .field("state_id", &self.state_id)
+            .finish()
+    }
+}
+
+impl<'a, F> LockFuture<'a, F> {
+    pub(crate) fn new(state_lock: &'a StateLock<F>, state_id: StateId) -> Self {
+        LockFuture {
+            state_lock,
+            state_id,
+            waiter: None,
+        }
+    }
+}
+
+impl<'a, F> Future for LockFuture<'a, F> {
+    type Output = io::Result<RawState<'a>>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let this = self.get_mut();
+        let waiter = match this.waiter.as_ref() {
+            Some(waiter) => waiter.clone(),
+            None => {
+                let waiter = Arc::new(AsyncWaiter::default());
+                let state_lock = this.state_lock;
+                if let Some(rsp) = state_lock.lock_or_register(&this.state_id, &waiter) {
+                    return Poll::Ready(rsp);
+                }
+                this.waiter.insert(waiter).clone()
+            }
+        };
+
+        // the response may already arrive before the waker is registered
+        match waiter.poll_rsp(cx.waker()) {
+            Some(rsp) => {
+                this.waiter = None;
+                Poll::Ready(rsp.map(RawState::new))
+            }
+            None => Poll::Pending,
+        }
+    }
+}
+
+impl<F> Drop for LockFuture<'_, F> {
+    fn drop(&mut self) {
+        // the future is dropped before the state is ready
+        if let Some(waiter) = self.waiter.take() {
+            let waiter = Waiter::Async(waiter);
+            if self.state_lock.remove_waiter(&self.state_id, &waiter) {
+                trace!("{} state async waiter {waiter:?} cancelled", self.state_id);
+            }
+        }
+    }
+}
@@ -0,0 +1,156 @@
+use crate::state::State;
+
+use std::any::Any;
+use std::fmt::{self, Debug, Display};
+use std::hash::{Hash, Hasher};
+use std::io;
+use std::marker::PhantomData;
+use std::sync::Arc;
+
+/// a state that is tear up with a runtime key, like "model X loaded with config Y"
+///
+/// the lock identity of a keyed state is `(state name, key)`, states with different keys
+/// are different states for `StateLock`, only waiters for the same key share the state.
+/// the state should still be registered in the family, use `StateLock::lock_with_key`
+/// to lock it with a key.
+pub trait KeyedState: State + Sized {
+    /// the parameter of the state
+    type Key: Hash + Eq + Debug + Send + Sync + 'static;
+
+    /// tear up the state with the key, the error would be returned to all the waiters
+    fn tear_up_with_key(key: &Self::Key) -> io::Result<Self>;
+}
+
+/// type erased key, knows how to tear up the keyed state
+trait ErasedKey: Send + Sync {
+    fn as_any(&self) -> &dyn Any;
+    fn key_eq(&self, other: &dyn ErasedKey) -> bool;
+    fn key_hash(&self, state: &mut dyn Hasher);
+    fn key_fmt(&self, f: &mut fmt::Formatter) -> fmt::Result;
+    fn tear_up(&self) -> io::Result<Box<dyn State>>;
+}
+
+struct TypedKey<T: KeyedState> {
+    key: T::Key,
+    _phantom: PhantomData<fn() -> T>,
+}
+
+impl<T: KeyedState> ErasedKey for TypedKey<T> {
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn key_eq(&self, other: &dyn ErasedKey) -> bool {
+        let other = other.as_any().downcast_ref::<Self>();
+        other.is_some_and(|other| other.key == self.key)
+    }
+
+    fn key_hash(&self, mut state: &mut dyn Hasher) {
+        self.key.hash(&mut state)
+    }
+
+    fn key_fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.key.fmt(f)
+    }
+
+    fn tear_up(&self) -> io::Result<Box<dyn State>> {
+        Ok(Box::new(T::tear_up_with_key(&self.key)?))
+    }
+}
+
+/// the key of a keyed state, cheap to clone
+#[derive(Clone)]
+pub(crate) struct StateKey(Arc<dyn ErasedKey>);
+
+impl StateKey {
+    pub(crate) fn new<T: KeyedState>(key: T::Key) -> Self {
+        StateKey(Arc::new(TypedKey::<T> {
+            key,
+            _phantom: PhantomData,
+        }))
+    }
+
+    /// tear up the keyed state with the key
+    pub(crate) fn tear_up(&self) -> io::Result<Box<dyn State>> {
+        self.0.tear_up()
+    }
+}
+
+impl PartialEq for StateKey {
+    fn eq(&self, other: &Self) -> bool {
+        self.0.key_eq(other.0.as_ref())
+    }
+}
+
+impl Eq for StateKey {}
+
+impl Hash for StateKey {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.0.key_hash(state)
+    }
+}
+
+impl Debug for StateKey {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.0.key_fmt(f)
+    }
+}
+
+/// the identity of a state in the waiter map, the key is `None` for normal states
+/// exclusive waiters are grouped apart from the shared ones
+#[derive(Clone, PartialEq, Eq, Hash)]
+pub(crate) struct StateId {
+    pub(crate) name: String,
+    pub(crate) key: Option<StateKey>,
+    pub(crate) exclusive: bool,
+}
+
+impl StateId {
+    pub(crate) fn new(name: &str) -> Self {
+        StateId {
+            name: name.to_string(),
+            key: None,
+            exclusive: false,
+        }
+    }
+
+    pub(crate) fn with_key(name: &str, key: StateKey) -> Self {
+        StateId {
+            name: name.to_string(),
+            key: Some(key),
+            exclusive: false,
+        }
+    }
+
+    /// the identity for the exclusive access of the state
+    pub(crate) fn exclusive(self) -> Self {
+        StateId {
+            exclusive: true,
+            ..self
+        }
+    }
+
+    /// check if the state has the same identity
+    pub(crate) fn is(&self, name: &str, key: Option<&StateKey>) -> bool {
+        self.name == name && self.key.as_ref() == key
+    }
+}
+
+impl Display for StateId {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match &self.key {
+            Some(key) => write!(f, "{}({key:?})", self.name)?,
+            None => write!(f, "{}", self.name)?,
+        }
+        if self.exclusive {
+            write!(f, " exclusive")?;
+        }
+        Ok(())
+    }
+}
+
+impl Debug for StateId {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        Display::fmt(self, f)
+    }
+}
@@ -7,20 +7,77 @@
 //!
 //! Multi thread could call `StateLock::lock` or `StateLock::lock_by_state_name` at the same time.
 //! If the state is ready, the thread would not block, else block until the state is ready.
+//!
+//! For async code, `StateLock::lock_async` or `StateLock::lock_by_state_name_async` return
+//! a future that could be polled by any async executor.
+//!
+//! `StateLock<F>` is typed with a state family, `StateLock::<family!(STATE_FAMILY)>::typed()`
+//! only locks the states of the family by type, which is checked at compile time.
+//!
+//! With the `tracing` feature, spans are emitted for the lock acquisitions, the state switches,
+//! the tear up and tear down of the states. the wait time is recorded in the acquisition span.
 
 #[macro_use]
 extern crate log;
 
+/// run the synchronous closure in a `tracing` span if the feature is enabled
+/// the span must not be entered across a wait, the coroutine may be resumed in another thread
+macro_rules! in_span {
+    ($f: expr, $($span: tt)+) => {{
+        #[cfg(feature = "tracing")]
+        let ret = tracing::info_span!($($span)+).in_scope($f);
+        #[cfg(not(feature = "tracing"))]
+        let ret = ($f)();
+        ret
+    }};
+}
+
 pub mod default;
 
 mod state;
-pub use state::{RawState, State, StateGuard};
+pub use state::{MappedStateGuard, RawState, State, StateGuard, StateGuardMut};
 
 mod lock;
-pub use lock::{CustomTearUpFn, StateLock};
+pub use lock::{CustomTearUpFn, PoisonPolicy, StateLock, TryCustomTearUpFn};
+
+mod family;
+pub use family::{family_id, AnyFamily, Family, FamilyMarker, MemberOf};
+
+mod owned;
+pub use owned::{OwnedRawState, OwnedStateGuard};
+
+mod future;
+pub use future::LockFuture;
+
+mod key;
+pub use key::KeyedState;
+
+mod set;
+pub use set::StateLockSet;
+
+mod cache;
+pub use cache::CacheCapacity;
+
+mod events;
+pub use events::StateEvent;
+
+mod stats;
+pub use stats::{Histogram, StateLockStats, StateStats};
+
+mod policy;
+pub use policy::{
+    Fifo, LargestGroupFirst, LeastRecentlyActive, LowestTransitionCost, PendingGroup,
+    SchedulingPolicy,
+};
+
+mod waiter;
 
 mod registry;
-pub use registry::{StateRegistration, STATE_REGISTRATION};
+pub use registry::{
+    CompatibilityRegistration, FallibleStateRegistration, StateRegistration,
+    TransitionCostRegistration, COMPATIBILITY_REGISTRATION, DEFAULT_TRANSITION_COST,
+    FALLIBLE_STATE_REGISTRATION, STATE_REGISTRATION, TRANSITION_COST_REGISTRATION,
+};
 
 // re-export #[derive(State)] for convenience
 pub use state_derive::State;
@@ -1,79 +1,164 @@
 use indexmap::IndexMap;
-use may::sync::Mutex;
+use may::go;
+use may::sync::mpsc::{self, Receiver, RecvTimeoutError, Sender};
+use may::sync::{Mutex, MutexGuard};
 use may_waiter::{TokenWaiter, ID};
 
-use crate::state::{RawState, State, StateGuard, StateWrapper};
+use crate::cache::{CacheCapacity, StateCache};
+use crate::events::{Events, StateEvent};
+use crate::family::{AnyFamily, Family, MemberOf};
+use crate::future::LockFuture;
+use crate::key::{KeyedState, StateId, StateKey};
+use crate::owned::{OwnedRawState, OwnedStateGuard};
+use crate::policy::{Fifo, PendingGroup, SchedulingPolicy};
+use crate::state::{
+    current_holder, panic_message, RawState, State, StateGuard, StateGuardMut, StateWrapper,
+};
+use crate::stats::{StateLockStats, Stats};
+use crate::waiter::{AsyncWaiter, Waiter, WaiterGroup, WaiterRsp};
 
+use std::collections::HashMap;
 use std::fmt::{self, Debug};
+use std::future::Future;
 use std::io;
-use std::sync::{Arc, Weak};
+use std::marker::PhantomData;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::{Arc, PoisonError, Weak};
+use std::time::{Duration, Instant};
 
 struct StateLockInner {
-    // waiter map, key is the state name, value is the waiters
-    map: IndexMap<String, Vec<ID>>,
-    // track the current state, static life time for self ref
-    state: Option<Weak<StateWrapper<'static>>>,
-    // track the last state, could be reused
-    last_state: Option<Box<dyn State>>,
+    // waiter map, key is the state identity, value is the waiters
+    map: IndexMap<StateId, WaiterGroup>,
+    // track the live states, static life time for self ref
+    // more than one state could be live if they are compatible with each other
+    states: Vec<Weak<StateWrapper<'static>>>,
+    // track the released states, could be reused
+    cache: StateCache,
+    // the time each state is released last time
+    last_active: HashMap<&'static str, Instant>,
+    // cached states that are idle for this duration would be tear down
+    idle_timeout: Option<Duration>,
+    // dropped with the state lock to wake up the idle state reaper
+    reaper: Option<Sender<()>>,
+    // statistics of the states
+    stats: Stats,
+    // new acquisitions are rejected after shutdown
+    shutdown: bool,
 }
 
 impl StateLockInner {
-    /// get last state name
-    fn last_state_name(&self) -> &str {
-        self.last_state.as_ref().map(|s| s.name()).unwrap_or("")
+    /// the live states, the released ones are removed from the list
+    /// the returned states must be dropped after the lock is released
+    fn live_states(&mut self) -> Vec<Arc<StateWrapper<'static>>> {
+        let mut live = Vec::with_capacity(self.states.len());
+        self.states.retain(|s| match s.upgrade() {
+            Some(s) => {
+                live.push(s);
+                true
+            }
+            None => false,
+        });
+        live
     }
 
-    /// clear the last stat
-    fn drop_last_state(&mut self) {
-        if let Some(mut state) = self.last_state.take() {
-            state.tear_down();
-            let old_state = state.name();
-            // we should drop the old state completely before setup the new state
-            drop(state);
-            trace!("{old_state} state is dropped");
-        }
+    /// the most recently released state name
+    fn last_active_state(&self) -> Option<&'static str> {
+        let last_active = self.last_active.iter().max_by_key(|(_, t)| **t);
+        last_active.map(|(name, _)| *name)
     }
 }
 
 unsafe impl Send for StateLockInner {}
 
+/// lock the inner state, a panic under the lock is already handled there
+fn lock_inner(inner: &Mutex<StateLockInner>) -> MutexGuard<'_, StateLockInner> {
+    inner.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+/// tear down the idle cached states until the state lock is dropped
+fn idle_state_reaper(inner: Weak<Mutex<StateLockInner>>, dropped: Receiver<()>) {
+    let mut next_check = Instant::now();
+    loop {
+        let wait = next_check.saturating_duration_since(Instant::now());
+        if let Err(RecvTimeoutError::Disconnected) = dropped.recv_timeout(wait) {
+            // the state lock is dropped
+            return;
+        }
+        let Some(inner) = inner.upgrade() else {
+            return;
+        };
+        let mut lock = lock_inner(&inner);
+        let idle_timeout = lock.idle_timeout.expect("no idle timeout");
+        let expire_at = lock.cache.evict_expired(idle_timeout);
+        // a state released later would expire after the next full timeout
+        next_check = expire_at.unwrap_or_else(|| Instant::now() + idle_timeout);
+    }
+}
+
 /// custom state tear up, input is state name
 pub type CustomTearUpFn = Box<dyn Fn(&str) -> Box<dyn State> + Send + Sync>;
 
+/// fallible custom state tear up, input is state name
+pub type TryCustomTearUpFn = Box<dyn Fn(&str) -> io::Result<Box<dyn State>> + Send + Sync>;
+
+/// how to handle the state whose guard holder panicked
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum PoisonPolicy {
+    /// the state is released as usual and could be reused, this is the default
+    #[default]
+    Reuse,
+    /// the state is tear down once released and would be tear up again for the next use
+    Rebuild,
+}
+
 /// `StateLock` that could be used to lock for a state.
 ///
 /// After call `StateLock::lock` a `StateGuard` would be returned,
 /// then you could use the `StateGuard` to access the state.
-pub struct StateLock {
-    inner: Mutex<StateLockInner>,
+///
+/// `StateLock<F>` is typed with a family, it only locks the members of the family by type.
+/// the name based API is the same for all the state locks.
+pub struct StateLock<F = AnyFamily> {
+    // shared with the background idle state reaper
+    inner: Arc<Mutex<StateLockInner>>,
     state_family: String,
-    pub(crate) custom_tear_up: Option<CustomTearUpFn>,
+    pub(crate) custom_tear_up: Option<TryCustomTearUpFn>,
+    scheduling_policy: Box<dyn SchedulingPolicy>,
+    // new arrivals for the live state would wait behind other state groups
+    fair: bool,
+    // subscribers of the state events
+    events: Arc<Events>,
+    poison_policy: PoisonPolicy,
+    // track the guard holders to detect the deadlock
+    deadlock_detection: bool,
+    _family: PhantomData<fn() -> F>,
 }
 
-impl Debug for StateLock {
+impl<F> Debug for StateLock<F> {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         f.debug_struct("StateLock")
             .field("state_family", &self.state_family)
-            .field("current_state", &self.current_state().map(|s| s.name()))
+            .field("current_states", &self.current_states())
             .finish()
     }
 }
 
+impl<F> Drop for StateLock<F> {
+    fn drop(&mut self) {
+        // no guard could outlive the state lock, only the cached states are left
+        let mut lock = lock_inner(&self.inner);
+        lock.shutdown = true;
+        lock.reaper = None;
+        lock.cache.clear();
+    }
+}
+
 impl StateLock {
     /// crate a new state lock with the given state family name.
     /// it will panic if the state family is not registered.
     /// all the state should impl `Default` for tear up logic
     pub fn new(state_family: &str) -> Self {
-        let count = crate::registry::state_names(state_family).count();
-        StateLock {
-            inner: Mutex::new(StateLockInner {
-                map: IndexMap::with_capacity(count),
-                state: None,
-                last_state: None,
-            }),
-            state_family: state_family.into(),
-            custom_tear_up: None,
-        }
+        Self::with_tear_up(state_family, None)
     }
 
     /// create a state lock with user specified tear-up logic
@@ -81,22 +166,250 @@ impl StateLock {
     where
         F: Fn(&str) -> Box<dyn State> + Send + Sync + 'static,
     {
+        Self::new_with_try_custom_tear_up(state_family, move |name| Ok(tear_up(name)))
+    }
+
+    /// create a state lock with user specified fallible tear-up logic
+    /// the tear up error would be returned to all the waiters of the state
+    pub fn new_with_try_custom_tear_up<F>(state_family: &str, tear_up: F) -> Self
+    where
+        F: Fn(&str) -> io::Result<Box<dyn State>> + Send + Sync + 'static,
+    {
+        Self::with_tear_up(state_family, Some(Box::new(tear_up)))
+    }
+
+    /// lock for a state by state concrete type
+    pub fn lock<T: State>(&self) -> io::Result<StateGuard<T>> {
+        self.lock_guard(None)
+    }
+
+    /// lock for a state by state concrete type, the guard owns the state lock
+    /// so it could be moved into other coroutines or threads
+    pub fn lock_owned<T: State>(self: &Arc<Self>) -> io::Result<OwnedStateGuard<T>> {
+        self.lock_guard_owned()
+    }
+
+    /// lock for a state by state concrete type, wait at most `timeout` for the state
+    pub fn lock_timeout<T: State>(&self, timeout: Duration) -> io::Result<StateGuard<'_, T>> {
+        self.lock_guard(Some(timeout))
+    }
+
+    /// try to lock for a state by state concrete type without blocking
+    /// return a `WouldBlock` error if a different state is active
+    pub fn try_lock<T: State>(&self) -> io::Result<StateGuard<'_, T>> {
+        self.try_lock_guard()
+    }
+
+    /// lock for a state by state concrete type without blocking the thread
+    /// except for tearing up the state, see `LockFuture`
+    pub fn lock_async<T: State>(&self) -> impl Future<Output = io::Result<StateGuard<'_, T>>> + '_ {
+        self.lock_guard_async()
+    }
+
+    /// lock for a keyed state with the key, the state is tear up by `KeyedState::tear_up_with_key`
+    /// waiters for the same state with different keys are in different groups
+    pub fn lock_with_key<T: KeyedState>(&self, key: T::Key) -> io::Result<StateGuard<'_, T>> {
+        self.lock_guard_with_key(key)
+    }
+
+    /// lock for a state with exclusive mutable access
+    /// it's granted only when no other guard holds the state, and the released state is reused
+    pub fn lock_exclusive<T: State>(&self) -> io::Result<StateGuardMut<'_, T>> {
+        self.lock_guard_exclusive()
+    }
+}
+
+impl<F> StateLock<F> {
+    fn with_tear_up(state_family: &str, custom_tear_up: Option<TryCustomTearUpFn>) -> Self {
         let count = crate::registry::state_names(state_family).count();
+        let events = Arc::new(Events::default());
         StateLock {
-            inner: Mutex::new(StateLockInner {
+            inner: Arc::new(Mutex::new(StateLockInner {
                 map: IndexMap::with_capacity(count),
-                state: None,
-                last_state: None,
-            }),
+                states: Vec::new(),
+                cache: StateCache::new(CacheCapacity::default(), events.clone()),
+                last_active: HashMap::with_capacity(count),
+                idle_timeout: None,
+                reaper: None,
+                stats: Stats::default(),
+                shutdown: false,
+            })),
             state_family: state_family.into(),
-            custom_tear_up: Some(Box::new(tear_up)),
+            custom_tear_up,
+            scheduling_policy: Box::new(Fifo),
+            fair: false,
+            events,
+            poison_policy: PoisonPolicy::default(),
+            deadlock_detection: false,
+            _family: PhantomData,
+        }
+    }
+
+    /// set the scheduling policy that choose the next state group
+    /// the default policy is `Fifo`
+    pub fn with_scheduling_policy<P: SchedulingPolicy + 'static>(mut self, policy: P) -> Self {
+        self.scheduling_policy = Box::new(policy);
+        self
+    }
+
+    /// set the capacity of the cache that keeps the released states for reuse
+    /// the default is `CacheCapacity::Lru(1)`, which only keeps the last state
+    pub fn with_state_cache(self, capacity: CacheCapacity) -> Self {
+        self.lock_inner().cache = StateCache::new(capacity, self.events.clone());
+        self
+    }
+
+    /// tear down the cached states in the background once they are idle for `timeout`
+    /// by default the cached states live until they are evicted for other states
+    pub fn with_idle_timeout(self, timeout: Duration) -> Self {
+        let mut lock = self.lock_inner();
+        // the running reaper would pick up the new timeout
+        if lock.idle_timeout.replace(timeout).is_none() {
+            let (tx, rx) = mpsc::channel();
+            lock.reaper = Some(tx);
+            let inner = Arc::downgrade(&self.inner);
+            go!(move || idle_state_reaper(inner, rx));
+        }
+        drop(lock);
+        self
+    }
+
+    /// enable the fair mode, the default is unfair
+    ///
+    /// in unfair mode, a new caller asking for the live state always joins it immediately,
+    /// so a steady stream of such callers could starve the other states.
+    /// in fair mode, once other states have waiters, the new caller for the live state
+    /// is queued behind them.
+    pub fn with_fairness(mut self, fair: bool) -> Self {
+        self.fair = fair;
+        self
+    }
+
+    /// set how to handle the state whose guard holder panicked
+    /// the default is `PoisonPolicy::Reuse`
+    pub fn with_poison_policy(mut self, policy: PoisonPolicy) -> Self {
+        self.poison_policy = policy;
+        self
+    }
+
+    /// detect the deadlock of a caller that locks another state while holding one,
+    /// the default is off
+    ///
+    /// the coroutine or thread that acquires a guard is its holder. it could lock the held
+    /// state again even in fair mode, and locking a state that the held states block returns
+    /// a `Deadlock` error instead of waiting forever. a guard moved to other coroutines is
+    /// still held by the one that acquired it, use the owned guards for them instead,
+    /// which are never tracked.
+    pub fn with_deadlock_detection(mut self, detect: bool) -> Self {
+        self.deadlock_detection = detect;
+        self
+    }
+
+    fn lock_inner(&self) -> MutexGuard<'_, StateLockInner> {
+        lock_inner(&self.inner)
+    }
+
+    /// subscribe the state change events
+    /// the events are emitted in order, the subscription ends when the receiver is dropped
+    pub fn subscribe(&self) -> Receiver<StateEvent> {
+        self.events.subscribe()
+    }
+
+    /// save the last state, the poisoned state may be tear down by the poison policy
+    pub(crate) fn save_last_state(
+        &self,
+        state: Box<dyn State>,
+        key: Option<StateKey>,
+        active: Duration,
+        poisoned: bool,
+    ) {
+        let mut lock = self.lock_inner();
+        lock.stats.record_release(state.name(), active);
+        // emit under the lock, so the events are in order
+        self.events.emit(StateEvent::Released {
+            state: state.name(),
+        });
+        lock.last_active.insert(state.name(), Instant::now());
+        if lock.shutdown {
+            lock.cache.tear_down(state);
+        } else if poisoned && self.poison_policy == PoisonPolicy::Rebuild {
+            warn!("{} state is poisoned, tear down it", state.name());
+            lock.cache.tear_down(state);
+        } else {
+            lock.cache.put(state, key);
         }
     }
 
-    /// save the last state
-    pub(crate) fn save_last_state(&self, state: Box<dyn State>) {
-        let mut lock = self.inner.lock().unwrap();
-        lock.last_state = Some(state);
+    /// return the names of the cached states, least recently used first
+    pub fn cached_states(&self) -> Vec<&'static str> {
+        self.lock_inner().cache.names().collect()
+    }
+
+    /// return a snapshot of the statistics of the states
+    pub fn stats(&self) -> StateLockStats {
+        let lock = self.lock_inner();
+        lock.stats.snapshot(&lock.cache.tear_down_time)
+    }
+
+    /// tear down all the cached states
+    pub fn clear_cache(&self) {
+        self.lock_inner().cache.clear();
+    }
+
+    /// tear down the cached states that are idle for at least the idle timeout right now
+    /// all the cached states are tear down if there is no idle timeout
+    pub fn evict_idle(&self) {
+        let mut lock = self.lock_inner();
+        match lock.idle_timeout {
+            Some(idle_timeout) => {
+                lock.cache.evict_expired(idle_timeout);
+            }
+            None => lock.cache.clear(),
+        }
+    }
+
+    /// shut down the state lock, wait at most `timeout` for the outstanding guards
+    ///
+    /// new acquisitions are rejected and the queued waiters are woken up with an error.
+    /// the cached states are tear down right now, the live states are tear down once
+    /// all their guards are released. return a `TimedOut` error if any guard is still held
+    pub fn shutdown(&self, timeout: Duration) -> io::Result<()> {
+        let mut lock = self.lock_inner();
+        lock.shutdown = true;
+        let groups = std::mem::take(&mut lock.map);
+        lock.cache.clear();
+        drop(lock);
+        for (id, waiters) in groups {
+            waiters.wakeup(&id, &Err(self.shutdown_error()));
+        }
+
+        let deadline = Instant::now() + timeout;
+        loop {
+            let live = self.lock_inner().live_states();
+            if live.is_empty() {
+                return Ok(());
+            }
+            let now = Instant::now();
+            if now >= deadline {
+                let names = live.iter().map(|s| s.name()).collect::<Vec<_>>();
+                let err_msg = format!("{} state is still held", names.join(", "));
+                return Err(io::Error::new(io::ErrorKind::TimedOut, err_msg));
+            }
+            // release the state refs before sleep, the last one would tear down the state
+            drop(live);
+            may::coroutine::sleep((deadline - now).min(Duration::from_millis(10)));
+        }
+    }
+
+    fn check_running(&self, lock: &StateLockInner) -> io::Result<()> {
+        match lock.shutdown {
+            true => Err(self.shutdown_error()),
+            false => Ok(()),
+        }
+    }
+
+    fn shutdown_error(&self) -> io::Error {
+        io::Error::other(format!("{} state lock is shutting down", self.state_family))
     }
 
     /// return the state family name
@@ -111,119 +424,891 @@ impl StateLock {
 
     /// get current state of the state lock
     /// if no task lock it, return None, or we return a `RawState`
+    /// if several compatible states are live, the earliest activated one is returned
     pub fn current_state(&self) -> Option<RawState> {
-        self.inner
-            .lock()
-            .unwrap()
-            .state
-            .as_ref()
-            .and_then(|s| s.upgrade())
-            .map(RawState::new)
+        self.current_states().into_iter().next()
+    }
+
+    /// get all the live states of the state lock, the earliest activated first
+    /// only the compatible states could be live at the same time,
+    /// the state held by a `StateGuardMut` is not included
+    pub fn current_states(&self) -> Vec<RawState<'_>> {
+        let live = self.lock_inner().live_states();
+        let live = live.into_iter().filter(|s| !s.is_exclusive());
+        live.map(RawState::new).collect()
     }
 
     /// lock for a state by it's name
     /// since we can't get the state type, we have to return a state wrapper
+    /// with the deadlock detection, return a `Deadlock` error
+    /// if the caller is holding a state of the lock that blocks it
     pub fn lock_by_state_name(&self, state_name: &str) -> io::Result<RawState> {
-        if !self.state_names().any(|name| name == state_name) {
-            let err_msg = format!("state {state_name} is not registered");
-            return Err(io::Error::other(err_msg));
+        self.check_state_name(state_name)?;
+        self.lock_by_id(&StateId::new(state_name), None)
+    }
+
+    /// lock for a state by it's name, the returned state owns the state lock
+    /// so it could be moved into other coroutines or threads
+    pub fn lock_by_state_name_owned(
+        self: &Arc<Self>,
+        state_name: &str,
+    ) -> io::Result<OwnedRawState<F>> {
+        self.check_state_name(state_name)?;
+        let state = self.acquire(&StateId::new(state_name), None)?;
+        Ok(OwnedRawState::new(self.clone(), state))
+    }
+
+    /// lock for a state by it's name, wait at most `timeout` for the state
+    /// return a `TimedOut` error if the state is not ready in time
+    pub fn lock_by_state_name_timeout(
+        &self,
+        state_name: &str,
+        timeout: Duration,
+    ) -> io::Result<RawState<'_>> {
+        self.check_state_name(state_name)?;
+        self.lock_by_id(&StateId::new(state_name), Some(timeout))
+    }
+
+    /// lock for a keyed state with the key
+    fn lock_guard_with_key<T: KeyedState>(&self, key: T::Key) -> io::Result<StateGuard<'_, T, F>> {
+        let state_name = T::state_name();
+        self.check_state_name(state_name)?;
+        let id = StateId::with_key(state_name, StateKey::new::<T>(key));
+        let state = self.lock_by_id(&id, None)?;
+        Ok(state.into_family_guard())
+    }
+
+    /// lock for a state with exclusive mutable access
+    fn lock_guard_exclusive<T: State>(&self) -> io::Result<StateGuardMut<'_, T, F>> {
+        let state_name = T::state_name();
+        self.check_state_name(state_name)?;
+        let id = StateId::new(state_name).exclusive();
+        let state = self.lock_by_id(&id, None)?;
+        Ok(state.into_guard_mut())
+    }
+
+    /// hold the state exclusively if there is no other guard
+    pub(crate) fn try_set_exclusive(&self, state: &Arc<StateWrapper>) -> bool {
+        // all the state refs are cloned under the lock
+        let _lock = self.lock_inner();
+        if Arc::strong_count(state) != 1 {
+            return false;
         }
+        state.set_exclusive(true);
+        true
+    }
+
+    /// release the exclusive access, the waiters could join the state now
+    pub(crate) fn clear_exclusive(&self, state: &StateWrapper) {
+        {
+            let _lock = self.lock_inner();
+            state.set_exclusive(false);
+        }
+        self.wakeup_next_group();
+    }
+
+    fn lock_by_id(&self, id: &StateId, timeout: Option<Duration>) -> io::Result<RawState<'_>> {
+        self.acquire(id, timeout).map(RawState::new)
+    }
 
-        let mut lock = self.inner.lock().unwrap();
-        if let Some(s) = lock.state.as_ref().and_then(|s| s.upgrade()) {
-            // if we are waiting for the same state, then just return
-            if s.name() == state_name {
+    /// acquire a reference of the state, the caller decides how to hold it
+    fn acquire(&self, id: &StateId, timeout: Option<Duration>) -> WaiterRsp {
+        // the span is only entered for the synchronous part, the wait time is recorded
+        #[cfg(feature = "tracing")]
+        let span = tracing::info_span!(
+            "state_lock.acquire",
+            family = %self.state_family,
+            state = %id,
+            exclusive = id.exclusive,
+            waited = tracing::field::Empty,
+            wait_us = tracing::field::Empty,
+        );
+        #[cfg(feature = "tracing")]
+        let granted = span.in_scope(|| self.grant_or_queue(id, None));
+        #[cfg(not(feature = "tracing"))]
+        let granted = self.grant_or_queue(id, None);
+        #[cfg(feature = "tracing")]
+        span.record("waited", matches!(granted, Ok(Granted::Queued(..))));
+
+        let (waiter, waiter_id) = match granted? {
+            Granted::Ready(s) => return Ok(s),
+            Granted::Queued(waiter, waiter_id) => (waiter, waiter_id),
+        };
+        // wait for the state to be setup
+        trace!("{id} state is waiting for setup");
+        // the wait span is never entered, the coroutine may yield while waiting
+        #[cfg(feature = "tracing")]
+        let wait_span = tracing::info_span!(parent: &span, "state_lock.wait", state = %id);
+        #[cfg(feature = "tracing")]
+        let start = Instant::now();
+        let state = self.wait_state(id, &waiter, waiter_id, timeout);
+        #[cfg(feature = "tracing")]
+        span.record("wait_us", start.elapsed().as_micros() as u64);
+        #[cfg(feature = "tracing")]
+        drop(wait_span);
+        trace!("{id} state wait done");
+        state
+    }
+
+    /// return the state if no need to wait, or queue a waiter for it
+    /// the `releasing` state is released once the waiter is queued
+    fn grant_or_queue(&self, id: &StateId, releasing: Option<RawState>) -> io::Result<Granted> {
+        let mut lock = self.lock_inner();
+        self.check_running(&lock)?;
+        let live = lock.live_states();
+        let mut grant = self.grant(&lock, &live, id);
+        if let (Grant::Wait, Some(s)) = (&grant, self.reentrant_state(&live, id)) {
+            // the reentrant lock never waits behind other groups, or it would deadlock
+            grant = Grant::Join(s);
+        }
+        match grant {
+            Grant::Join(s) => {
+                // if we are waiting for the same state, then just return
+                lock.stats.record_acquire(s.name(), 1);
+                drop(lock);
                 trace!("{} state is already locked", s.name());
-                return Ok(RawState::new(s));
-            }
-
-            // we have to wait until the state is setup
-            let waiter = TokenWaiter::new();
-            let waiters = lock.map.entry(state_name.to_string()).or_default();
-
-            // insert the waiter into the waiters queue
-            let id = waiter.id().unwrap();
-            trace!("{state_name} state register a waiter {id:?} ");
-            waiters.push(id);
-            // release the lock and let other thread to access the state lock
-            drop(lock);
-            // release the state ref before wait for the state to be setup
-            // drop the state after release the lock, it may use the lock in sate drop
-            drop(s);
-
-            // wait for the state to be setup
-            trace!("{state_name} state is waiting for setup");
-            let state = waiter.wait_rsp(None)?;
-            trace!("{state_name} state wait done");
-            Ok(RawState::new(state))
+                Ok(Granted::Ready(s))
+            }
+            Grant::Activate => self.activate_live(lock, id).map(Granted::Ready),
+            Grant::Wait => {
+                let releasing_state = releasing.as_ref().map(|s| s.wrapper());
+                let blockers = self.held_blockers(&lock, &live, id, releasing_state);
+                if !blockers.is_empty() {
+                    drop(lock);
+                    let err_msg = if releasing.is_some() {
+                        format!(
+                            "switch to {id} state would deadlock, the caller is holding other guards"
+                        )
+                    } else {
+                        format!(
+                            "lock {id} state would deadlock, the caller is holding {} state",
+                            blockers.join(", ")
+                        )
+                    };
+                    return Err(io::Error::new(io::ErrorKind::Deadlock, err_msg));
+                }
+
+                // we have to wait until the state is setup
+                let (waiter, waiter_id) = self.push_waiter(&mut lock, id);
+                // release the lock and let other thread to access the state lock
+                drop(lock);
+                // the released state may wake up the group we are waiting for
+                drop(releasing);
+                // release the state ref before wait for the state to be setup
+                // drop the state after release the lock, it may use the lock in sate drop
+                drop(live);
+                Ok(Granted::Queued(waiter, waiter_id))
+            }
+        }
+    }
+
+    /// release the state and lock for another state in one critical section
+    /// the caller is at least in the next group of the state
+    pub(crate) fn switch_state<'a>(
+        &'a self,
+        state: RawState<'a>,
+        state_name: &str,
+    ) -> io::Result<RawState<'a>> {
+        self.check_state_name(state_name)?;
+        let id = StateId::new(state_name);
+
+        let from = state.name();
+        #[cfg(feature = "tracing")]
+        let span = tracing::info_span!(
+            "state_lock.switch",
+            family = %self.state_family,
+            from,
+            to = %id,
+        );
+        #[cfg(feature = "tracing")]
+        let granted = span.in_scope(|| self.grant_or_queue(&id, Some(state)))?;
+        #[cfg(not(feature = "tracing"))]
+        let granted = self.grant_or_queue(&id, Some(state))?;
+        match granted {
+            Granted::Ready(s) => Ok(RawState::new(s)),
+            Granted::Queued(waiter, waiter_id) => {
+                trace!("{id} state is waiting for switch, released {from} state");
+                #[cfg(feature = "tracing")]
+                let wait_span = tracing::info_span!(parent: &span, "state_lock.wait", state = %id);
+                let state = self.wait_state(&id, &waiter, waiter_id, None);
+                #[cfg(feature = "tracing")]
+                drop(wait_span);
+                Ok(RawState::new(state?))
+            }
+        }
+    }
+
+    /// try to lock for a state by it's name without blocking
+    /// return a `WouldBlock` error if a different state is active
+    pub fn try_lock_by_state_name(&self, state_name: &str) -> io::Result<RawState<'_>> {
+        let try_lock = || {
+            self.check_state_name(state_name)?;
+            let id = StateId::new(state_name);
+
+            let mut lock = self.lock_inner();
+            self.check_running(&lock)?;
+            let live = lock.live_states();
+            match self.grant(&lock, &live, &id) {
+                Grant::Join(s) => {
+                    lock.stats.record_acquire(s.name(), 1);
+                    drop(lock);
+                    trace!("{} state is already locked", s.name());
+                    Ok(RawState::new(s))
+                }
+                Grant::Activate => self.activate_live(lock, &id).map(RawState::new),
+                Grant::Wait => {
+                    drop(lock);
+                    let err_msg = if let Some(s) = live.iter().find(|s| s.is(&id)) {
+                        if s.is_exclusive() {
+                            format!("{state_name} state is held exclusively")
+                        } else {
+                            format!("other states are waiting before {state_name} state")
+                        }
+                    } else {
+                        let names = live.iter().map(|s| s.name()).collect::<Vec<_>>();
+                        format!("{} state is active", names.join(", "))
+                    };
+                    Err(io::Error::new(io::ErrorKind::WouldBlock, err_msg))
+                }
+            }
+        };
+        in_span!(
+            try_lock,
+            "state_lock.try_lock",
+            family = %self.state_family,
+            state = state_name,
+        )
+    }
+
+    /// try to lock for a state by state concrete type without blocking
+    fn try_lock_guard<T: State>(&self) -> io::Result<StateGuard<'_, T, F>> {
+        let state_name = T::state_name();
+        let state = self.try_lock_by_state_name(state_name)?;
+        Ok(state.into_family_guard())
+    }
+
+    /// only attach to the state if it's the current active state
+    /// never tear up a state, return a `WouldBlock` error if the state is not active
+    pub fn lock_if_current(&self, state_name: &str) -> io::Result<RawState<'_>> {
+        self.check_state_name(state_name)?;
+        let id = StateId::new(state_name);
+
+        let mut lock = self.lock_inner();
+        self.check_running(&lock)?;
+        let live = lock.live_states();
+        let grant = self.grant(&lock, &live, &id);
+        if let Grant::Join(s) = &grant {
+            lock.stats.record_acquire(s.name(), 1);
+        }
+        // drop the state after release the lock, it may use the lock in sate drop
+        drop(lock);
+        match grant {
+            Grant::Join(s) => Ok(RawState::new(s)),
+            _ => {
+                let err_msg = format!("{state_name} state is not active");
+                Err(io::Error::new(io::ErrorKind::WouldBlock, err_msg))
+            }
+        }
+    }
+
+    /// decide how the caller could get the state
+    fn grant(
+        &self,
+        lock: &StateLockInner,
+        live: &[Arc<StateWrapper<'static>>],
+        id: &StateId,
+    ) -> Grant {
+        // no live state, just tear up the state
+        if live.is_empty() {
+            return Grant::Activate;
+        }
+        // in fair mode, wait behind other state groups
+        if self.fair && !lock.map.is_empty() {
+            return Grant::Wait;
+        }
+        if let Some(s) = live.iter().find(|s| s.is(id)) {
+            // the exclusive access has to wait for all the guards released
+            if id.exclusive || s.is_exclusive() {
+                return Grant::Wait;
+            }
+            Grant::Join(s.clone())
+        } else if self.is_compatible(live, id) {
+            Grant::Activate
         } else {
-            let state = if state_name == lock.last_state_name() {
-                // reuse the last state
-                Arc::new(StateWrapper::new(self, lock.last_state.take()))
-            } else {
-                // first clear the last state if any
-                lock.drop_last_state();
-                // create a new state
-                Arc::new(StateWrapper::new_from_name(self, state_name).unwrap())
+            Grant::Wait
+        }
+    }
+
+    /// the live state that the caller is holding and could share with itself
+    fn reentrant_state(
+        &self,
+        live: &[Arc<StateWrapper<'static>>],
+        id: &StateId,
+    ) -> Option<Arc<StateWrapper<'static>>> {
+        if !self.deadlock_detection || id.exclusive {
+            return None;
+        }
+        let holder = current_holder();
+        let state = live
+            .iter()
+            .find(|s| s.is(id) && !s.is_exclusive() && s.is_held_by(holder));
+        state.cloned()
+    }
+
+    /// the live states held by the caller that block the state, except the `releasing` one
+    /// waiting for the state while holding one of them would never return
+    fn held_blockers(
+        &self,
+        lock: &StateLockInner,
+        live: &[Arc<StateWrapper<'static>>],
+        id: &StateId,
+        releasing: Option<&StateWrapper>,
+    ) -> Vec<&'static str> {
+        if !self.deadlock_detection {
+            return Vec::new();
+        }
+        let holder = current_holder();
+        // in fair mode the caller waits behind other groups, which may wait for any held state
+        let queued = self.fair && !lock.map.is_empty();
+        let is_blocker = |s: &StateWrapper| {
+            queued || !crate::registry::is_compatible(&self.state_family, s.name(), &id.name)
+        };
+        let held = live.iter().filter(|s| {
+            let held = s.held_count(holder);
+            let held = match releasing {
+                Some(r) if s.is_same(r) => held > 1,
+                _ => held > 0,
             };
+            held && is_blocker(s)
+        });
+        held.map(|s| s.name()).collect()
+    }
+
+    /// queue a waiter for the state
+    fn push_waiter(&self, lock: &mut StateLockInner, id: &StateId) -> (TokenWaiter<WaiterRsp>, ID) {
+        let waiter = TokenWaiter::<WaiterRsp>::new();
+        let waiters = lock.map.entry(id.clone()).or_default();
+
+        // insert the waiter into the waiters queue
+        let waiter_id = waiter.id().unwrap();
+        trace!("{id} state register a waiter {waiter_id:?} ");
+        waiters.push(Waiter::Token(waiter_id));
+        self.emit_waiter_queued(id);
+        (waiter, waiter_id)
+    }
+
+    /// check if the state could be live together with all the live states
+    fn is_compatible(&self, live: &[Arc<StateWrapper<'static>>], id: &StateId) -> bool {
+        live.iter()
+            .all(|s| crate::registry::is_compatible(&self.state_family, s.name(), &id.name))
+    }
+
+    fn emit_waiter_queued(&self, id: &StateId) {
+        // the state name is already checked
+        if let Some(state) = self.state_names().find(|name| *name == id.name) {
+            self.events.emit(StateEvent::WaiterQueued { state });
+        }
+    }
 
-            lock.state = Some(Arc::downgrade(&state));
-            let waiter_ids = lock.map.swap_remove(state_name);
-            drop(lock);
+    /// check if the state name is registered in the state family
+    fn check_state_name(&self, state_name: &str) -> io::Result<()> {
+        if !self.state_names().any(|name| name == state_name) {
+            let err_msg = format!("state {state_name} is not registered");
+            return Err(io::Error::other(err_msg));
+        }
+        Ok(())
+    }
 
-            trace!("{state_name} state is set from empty");
-            // wake up all waiters waiting for the same state
-            if let Some(ids) = waiter_ids {
-                for waiter_id in ids {
-                    trace!("wakeup {state_name} state, waiter {waiter_id:?} (with same state)");
-                    TokenWaiter::set_rsp(waiter_id, state.clone());
+    /// set up the state when there is no live state or it's compatible with the live states
+    fn activate_live(&self, mut lock: MutexGuard<'_, StateLockInner>, id: &StateId) -> WaiterRsp {
+        let state = match self.activate_state(&mut lock, id) {
+            Ok(state) => state,
+            Err(e) => {
+                warn!("{id} state tear up failed: {e}");
+                // all waiters waiting for the same state share the failure
+                let waiters = lock.map.swap_remove(id);
+                drop(lock);
+                let rsp = Err(e);
+                if let Some(waiters) = waiters {
+                    waiters.wakeup(id, &rsp);
                 }
+                // other state groups may still be queued
+                self.wakeup_next_group();
+                return rsp;
             }
+        };
+
+        lock.states.push(Arc::downgrade(&state));
+        // other exclusive waiters have to wait for this one
+        let waiters = if id.exclusive {
+            state.set_exclusive(true);
+            None
+        } else {
+            lock.map.swap_remove(id)
+        };
+        lock.stats.record_acquire(state.name(), 1);
+        if let Some(waiters) = waiters.as_ref() {
+            lock.stats.record_wakeup(state.name(), waiters);
+        }
+        drop(lock);
 
-            Ok(RawState::new(state))
+        trace!("{id} state is activated");
+        // wake up all waiters waiting for the same state
+        let rsp = Ok(state);
+        if let Some(waiters) = waiters {
+            waiters.wakeup(id, &rsp);
         }
+
+        rsp
     }
 
-    /// lock for a state by state concrete type
-    pub fn lock<T: State>(&self) -> io::Result<StateGuard<T>> {
+    /// lock for a state by state concrete type, wait at most `timeout` if any
+    fn lock_guard<T: State>(&self, timeout: Option<Duration>) -> io::Result<StateGuard<'_, T, F>> {
         let state_name = T::state_name();
-        let state = self.lock_by_state_name(state_name)?;
+        self.check_state_name(state_name)?;
+        let state = self.lock_by_id(&StateId::new(state_name), timeout)?;
+        Ok(state.into_family_guard())
+    }
+
+    /// lock for a state by state concrete type, the guard owns the state lock
+    fn lock_guard_owned<T: State>(self: &Arc<Self>) -> io::Result<OwnedStateGuard<T, F>> {
+        let state_name = T::state_name();
+        let state = self.lock_by_state_name_owned(state_name)?;
         Ok(state.into_guard())
     }
 
-    /// wait up all the waiters that are waiting for the state
-    pub(crate) fn wakeup_next_group(&self) {
-        let mut lock = self.inner.lock().unwrap();
-        {
-            // we already have live state, no need to wakeup
-            if lock.state.as_ref().and_then(|s| s.upgrade()).is_some() {
-                drop(lock);
-                return;
+    /// lock for a state by it's name without blocking the thread
+    /// the returned future could be polled by any async executor
+    /// the first poll may tear up the state in place and block the executor thread
+    pub fn lock_by_state_name_async(&self, state_name: &str) -> LockFuture<'_, F> {
+        LockFuture::new(self, StateId::new(state_name))
+    }
+
+    /// lock for a state by state concrete type without blocking the thread
+    fn lock_guard_async<T: State>(
+        &self,
+    ) -> impl Future<Output = io::Result<StateGuard<'_, T, F>>> + '_ {
+        let state = self.lock_by_state_name_async(T::state_name());
+        async move { Ok(state.await?.into_family_guard()) }
+    }
+
+    /// return the state directly if no need to wait, or register the async waiter
+    pub(crate) fn lock_or_register(
+        &self,
+        id: &StateId,
+        waiter: &Arc<AsyncWaiter>,
+    ) -> Option<io::Result<RawState<'_>>> {
+        let lock_or_register = || {
+            if let Err(e) = self.check_state_name(&id.name) {
+                return Some(Err(e));
+            }
+
+            let mut lock = self.lock_inner();
+            if let Err(e) = self.check_running(&lock) {
+                return Some(Err(e));
+            }
+            let live = lock.live_states();
+            match self.grant(&lock, &live, id) {
+                Grant::Join(s) => {
+                    lock.stats.record_acquire(s.name(), 1);
+                    drop(lock);
+                    trace!("{} state is already locked", s.name());
+                    Some(Ok(RawState::new(s)))
+                }
+                Grant::Activate => Some(self.activate_live(lock, id).map(RawState::new)),
+                Grant::Wait => {
+                    let waiter = Waiter::Async(waiter.clone());
+                    trace!("{id} state register an async waiter {waiter:?}");
+                    lock.map.entry(id.clone()).or_default().push(waiter);
+                    self.emit_waiter_queued(id);
+                    // drop the state after release the lock, it may use the lock in sate drop
+                    drop(lock);
+                    None
+                }
             }
+        };
+        in_span!(
+            lock_or_register,
+            "state_lock.acquire_async",
+            family = %self.state_family,
+            state = %id,
+        )
+    }
+
+    /// wait for the response of the state group
+    fn wait_state(
+        &self,
+        id: &StateId,
+        waiter: &TokenWaiter<WaiterRsp>,
+        waiter_id: ID,
+        timeout: Option<Duration>,
+    ) -> WaiterRsp {
+        match self.wait_rsp(id, waiter, waiter_id, timeout) {
+            Ok(rsp) => rsp,
+            Err(e) if e.kind() == io::ErrorKind::TimedOut => {
+                // give up waiting, the group should not be activated for us
+                if self.remove_waiter(id, &Waiter::Token(waiter_id)) {
+                    trace!("{id} state waiter {waiter_id:?} timeout");
+                    return Err(e);
+                }
+                // the group is already taken, the response is on the way
+                self.wait_rsp(id, waiter, waiter_id, None)?
+            }
+            Err(e) => Err(e),
+        }
+    }
+
+    /// wait for the response, the coroutine may be cancelled while waiting
+    fn wait_rsp(
+        &self,
+        id: &StateId,
+        waiter: &TokenWaiter<WaiterRsp>,
+        waiter_id: ID,
+        timeout: Option<Duration>,
+    ) -> io::Result<WaiterRsp> {
+        let cancel_guard = CancelGuard {
+            state_lock: self,
+            id,
+            waiter_id,
+        };
+        let rsp = waiter.wait_rsp(timeout);
+        std::mem::forget(cancel_guard);
+        rsp
+    }
+
+    /// remove the waiter from the waiter queue
+    /// return false if the waiter is not in the queue, which means it's already woken up
+    pub(crate) fn remove_waiter(&self, id: &StateId, waiter: &Waiter) -> bool {
+        let mut lock = self.lock_inner();
+        let Some(waiters) = lock.map.get_mut(id) else {
+            return false;
+        };
+        if !waiters.remove(waiter) {
+            return false;
         }
-        // have to wake up next group
-        if let Some((new_state, waiters)) = lock.map.shift_remove_index(0) {
+        if waiters.is_empty() {
+            // keep the order of other groups
+            lock.map.shift_remove(id);
+        }
+        drop(lock);
+
+        // the next groups may only wait for the removed group
+        self.wakeup_next_group();
+        true
+    }
+
+    /// wait up the next groups in the scheduling order
+    /// until a group has to wait for the live states
+    pub(crate) fn wakeup_next_group(&self) {
+        let mut lock = self.lock_inner();
+        let mut live = lock.live_states();
+        let mut wakeups = Vec::new();
+        // the groups that could not be woken up now, they wait for the next release
+        let mut skipped = Vec::new();
+        // skip the groups that failed to tear up
+        while let Some(index) = self.pick_next_group(&lock, &skipped) {
+            let (id, _) = lock.map.get_index(index).expect("invalid group index");
+            // the group of a live state is queued in fair mode or when it's held exclusively
+            let joined = live.iter().find(|s| s.is(id)).cloned();
+            let can_wakeup = match &joined {
+                // in fair mode, the group of a live state never jumps ahead of a blocked group
+                Some(s) => !id.exclusive && !s.is_exclusive() && (!self.fair || skipped.is_empty()),
+                None => self.is_compatible(&live, id),
+            };
+            if !can_wakeup {
+                // keep scanning, a later group may be compatible with the live states
+                skipped.push(id.clone());
+                continue;
+            }
+
+            let (new_state, waiters) = if id.exclusive {
+                // only one exclusive waiter could be woken up
+                let (id, waiters) = lock.map.get_index_mut(index).unwrap();
+                let (id, first) = (id.clone(), waiters.split_first());
+                if waiters.is_empty() {
+                    lock.map.shift_remove_index(index);
+                }
+                (id, first)
+            } else {
+                lock.map.shift_remove_index(index).unwrap()
+            };
             trace!("wakeup_next_group to state {new_state}");
-            // first clear the last state if any
-            lock.drop_last_state();
-            // create a new state from the id
-            let state = StateWrapper::new_from_name(self, &new_state);
-            let state = Arc::new(state.expect("state name not found"));
-
-            // need first drop the old state
-            lock.state.replace(Arc::downgrade(&state));
-            trace!("{} state is set from last state", state.name());
-
-            // must first drop the lock, then wakeup the waiters
-            drop(lock);
-            // wait up all the waiters that are waiting for the state
-            for waiter_id in waiters {
-                trace!("wakeup {new_state} state, waiter {waiter_id:?}");
-                TokenWaiter::set_rsp(waiter_id, state.clone());
+            let rsp = match joined {
+                Some(state) => Ok(state),
+                None => match self.activate_state(&mut lock, &new_state) {
+                    Ok(state) => {
+                        state.set_exclusive(new_state.exclusive);
+                        lock.states.push(Arc::downgrade(&state));
+                        live.push(state.clone());
+                        trace!("{new_state} state is activated");
+                        Ok(state)
+                    }
+                    Err(e) => {
+                        warn!("{new_state} state tear up failed: {e}");
+                        Err(e)
+                    }
+                },
+            };
+            if let Ok(state) = &rsp {
+                lock.stats.record_wakeup(state.name(), &waiters);
             }
-        } else {
+            wakeups.push((new_state, waiters, rsp));
+        }
+
+        if live.is_empty() {
             trace!("state cleared!!!!");
-            lock.state = None
         }
+        // must first drop the lock, then wakeup the waiters
+        drop(lock);
+        for (new_state, waiters, rsp) in wakeups {
+            // wait up all the waiters that are waiting for the state
+            waiters.wakeup(&new_state, &rsp);
+        }
+        drop(live);
+    }
+
+    /// activate the state and notify the subscribers
+    fn activate_state(
+        &self,
+        lock: &mut StateLockInner,
+        id: &StateId,
+    ) -> io::Result<Arc<StateWrapper<'static>>> {
+        let state = self.reuse_or_tear_up(lock, id)?;
+        self.events.emit(StateEvent::Activated {
+            state: state.name(),
+        });
+        Ok(Arc::new(state))
+    }
+
+    /// reuse the state from cache, convert from a cached state, or tear up a new one
+    fn reuse_or_tear_up(
+        &self,
+        lock: &mut StateLockInner,
+        id: &StateId,
+    ) -> io::Result<StateWrapper<'static>> {
+        if let Some(state) = lock.cache.take(id) {
+            lock.stats.record_activate(state.name(), true);
+            return Ok(StateWrapper::new(self, state, id.key.clone()));
+        }
+        // try to convert a released state into the new state, keyed states need the key
+        if id.key.is_none() {
+            if let Some(state) = lock.cache.transition(&id.name) {
+                lock.stats.record_activate(state.name(), false);
+                return Ok(StateWrapper::new(self, state, None));
+            }
+        }
+        // first make room for the new state
+        lock.cache.make_room(&id.name);
+        // create a new state
+        if let Some(state) = self.state_names().find(|name| *name == id.name) {
+            self.events.emit(StateEvent::TearUpStarted { state });
+        }
+        let start = Instant::now();
+        let state = StateWrapper::new_from_id(self, id)?;
+        lock.stats.record_tear_up(state.name(), start.elapsed());
+        lock.stats.record_activate(state.name(), false);
+        Ok(state)
+    }
+
+    /// return the index of the next state group in the waiter map by the scheduling policy
+    /// the `skipped` groups are not considered
+    fn pick_next_group(&self, lock: &StateLockInner, skipped: &[StateId]) -> Option<usize> {
+        let candidates = (0..lock.map.len())
+            .filter(|&i| !skipped.contains(lock.map.get_index(i).unwrap().0))
+            .collect::<Vec<_>>();
+        match candidates.len() {
+            0 => return None,
+            1 => return Some(candidates[0]),
+            _ => {}
+        }
+
+        let now = Instant::now();
+        let groups = candidates
+            .iter()
+            .map(|&i| lock.map.get_index(i).unwrap())
+            .map(|(id, waiters)| PendingGroup {
+                state_family: &self.state_family,
+                state_name: &id.name,
+                waiter_count: waiters.len(),
+                oldest_wait: waiters.oldest().map(|t| now - t).unwrap_or_default(),
+                last_active: lock.last_active.get(id.name.as_str()).copied(),
+            })
+            .collect::<Vec<_>>();
+        let last_state = lock.last_active_state();
+        let pick_next = AssertUnwindSafe(|| self.scheduling_policy.pick_next(last_state, &groups));
+        // a panic in the policy falls back to the first group
+        let index = panic::catch_unwind(pick_next).unwrap_or_else(|e| {
+            warn!("scheduling policy panicked: {}", panic_message(&*e));
+            0
+        });
+        // fallback to the first group for an invalid index
+        let index = if index < groups.len() { index } else { 0 };
+        Some(candidates[index])
+    }
+}
+
+impl<F: Family> StateLock<F> {
+    /// create a state lock for the family
+    /// the states are tear up by the registered logic
+    pub fn typed() -> Self {
+        Self::with_tear_up(F::family_name(), None)
+    }
+
+    /// create a state lock for the family with user specified tear-up logic
+    pub fn typed_with_custom_tear_up<T>(tear_up: T) -> Self
+    where
+        T: Fn(&str) -> Box<dyn State> + Send + Sync + 'static,
+    {
+        Self::typed_with_try_custom_tear_up(move |name| Ok(tear_up(name)))
+    }
+
+    /// create a state lock for the family with user specified fallible tear-up logic
+    /// the tear up error would be returned to all the waiters of the state
+    pub fn typed_with_try_custom_tear_up<T>(tear_up: T) -> Self
+    where
+        T: Fn(&str) -> io::Result<Box<dyn State>> + Send + Sync + 'static,
+    {
+        Self::with_tear_up(F::family_name(), Some(Box::new(tear_up)))
+    }
+
+    /// lock for a state of the family
+    pub fn lock<T: MemberOf<F>>(&self) -> io::Result<StateGuard<'_, T, F>> {
+        self.lock_guard(None)
+    }
+
+    /// lock for a state of the family, the guard owns the state lock
+    pub fn lock_owned<T: MemberOf<F>>(self: &Arc<Self>) -> io::Result<OwnedStateGuard<T, F>> {
+        self.lock_guard_owned()
+    }
+
+    /// lock for a state of the family, wait at most `timeout` for the state
+    pub fn lock_timeout<T: MemberOf<F>>(
+        &self,
+        timeout: Duration,
+    ) -> io::Result<StateGuard<'_, T, F>> {
+        self.lock_guard(Some(timeout))
+    }
+
+    /// try to lock for a state of the family without blocking
+    pub fn try_lock<T: MemberOf<F>>(&self) -> io::Result<StateGuard<'_, T, F>> {
+        self.try_lock_guard()
     }
+
+    /// lock for a state of the family without blocking the thread
+    pub fn lock_async<T: MemberOf<F>>(
+        &self,
+    ) -> impl Future<Output = io::Result<StateGuard<'_, T, F>>> + '_ {
+        self.lock_guard_async()
+    }
+
+    /// lock for a keyed state of the family with the key
+    pub fn lock_with_key<T>(&self, key: T::Key) -> io::Result<StateGuard<'_, T, F>>
+    where
+        T: KeyedState + MemberOf<F>,
+    {
+        self.lock_guard_with_key(key)
+    }
+
+    /// lock for a state of the family with exclusive mutable access
+    pub fn lock_exclusive<T: MemberOf<F>>(&self) -> io::Result<StateGuardMut<'_, T, F>> {
+        self.lock_guard_exclusive()
+    }
+}
+
+/// the state lock seen by its states, the family type is not needed there
+pub(crate) trait StateOwner {
+    fn save_last_state(
+        &self,
+        state: Box<dyn State>,
+        key: Option<StateKey>,
+        active: Duration,
+        poisoned: bool,
+    );
+
+    fn wakeup_next_group(&self);
+
+    fn switch_state<'a>(
+        &'a self,
+        state: RawState<'a>,
+        state_name: &str,
+    ) -> io::Result<RawState<'a>>;
+
+    fn try_set_exclusive(&self, state: &Arc<StateWrapper>) -> bool;
+
+    fn clear_exclusive(&self, state: &StateWrapper);
+
+    fn detects_deadlock(&self) -> bool;
+}
+
+impl<F> StateOwner for StateLock<F> {
+    fn save_last_state(
+        &self,
+        state: Box<dyn State>,
+        key: Option<StateKey>,
+        active: Duration,
+        poisoned: bool,
+    ) {
+        StateLock::save_last_state(self, state, key, active, poisoned)
+    }
+
+    fn wakeup_next_group(&self) {
+        StateLock::wakeup_next_group(self)
+    }
+
+    fn switch_state<'a>(
+        &'a self,
+        state: RawState<'a>,
+        state_name: &str,
+    ) -> io::Result<RawState<'a>> {
+        StateLock::switch_state(self, state, state_name)
+    }
+
+    fn try_set_exclusive(&self, state: &Arc<StateWrapper>) -> bool {
+        StateLock::try_set_exclusive(self, state)
+    }
+
+    fn clear_exclusive(&self, state: &StateWrapper) {
+        StateLock::clear_exclusive(self, state)
+    }
+
+    fn detects_deadlock(&self) -> bool {
+        self.deadlock_detection
+    }
+}
+
+/// remove the waiter from the waiter queue when the waiting coroutine is cancelled
+///
+/// the cancellation unwinds the coroutine stack, the guard is forgotten after waiting.
+/// if the group is already taken, the response is dropped with the waiter.
+struct CancelGuard<'a, F> {
+    state_lock: &'a StateLock<F>,
+    id: &'a StateId,
+    waiter_id: ID,
+}
+
+impl<F> Drop for CancelGuard<'_, F> {
+    fn drop(&mut self) {
+        let waiter = Waiter::Token(self.waiter_id);
+        if self.state_lock.remove_waiter(self.id, &waiter) {
+            trace!("{} state waiter {:?} cancelled", self.id, self.waiter_id);
+        }
+    }
+}
+
+/// the result of the synchronous part of an acquisition
+enum Granted {
+    /// the state is ready to use
+    Ready(Arc<StateWrapper<'static>>),
+    /// the waiter is queued, wait for the response
+    Queued(TokenWaiter<WaiterRsp>, ID),
+}
+
+/// how a caller could get the state
+enum Grant {
+    /// join the live state
+    Join(Arc<StateWrapper<'static>>),
+    /// activate the state, it's compatible with the live states
+    Activate,
+    /// wait for the live states to be released
+    Wait,
 }
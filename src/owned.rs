@@ -0,0 +1,123 @@
+use crate::state::{Held, State, StateWrapper};
+use crate::{AnyFamily, StateLock};
+
+use std::fmt::{self, Debug};
+use std::marker::PhantomData;
+use std::ops::Deref;
+use std::sync::Arc;
+
+/// general state that owns the `StateLock`, it could be moved into other coroutines
+///
+/// owned states could be held by any coroutine, they are not tracked by the deadlock detection
+///
+/// the state still refers to the state lock with an erased `'static` lifetime like other guards.
+/// the state is shared by all the guards of it, including the ones that borrow the state lock,
+/// so removing the erasure only for the owned guards is out of scope. it's sound because
+/// the owned `Arc<StateLock>` is only released after the state is dropped
+pub struct OwnedRawState<F = AnyFamily> {
+    // the fields are dropped in order, the state is released before the state lock
+    state: Held<'static>,
+    state_lock: Arc<StateLock<F>>,
+}
+
+impl<F> Clone for OwnedRawState<F> {
+    fn clone(&self) -> Self {
+        OwnedRawState {
+            state: self.state.clone(),
+            state_lock: self.state_lock.clone(),
+        }
+    }
+}
+
+impl<F> Debug for OwnedRawState<F> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "OwnedRawState{{ {}: {} }}",
+            self.state.family(),
+            self.state.name()
+        )
+    }
+}
+
+impl<F> OwnedRawState<F> {
+    pub(crate) fn new(state_lock: Arc<StateLock<F>>, state: Arc<StateWrapper<'static>>) -> Self {
+        OwnedRawState {
+            state: Held::untracked(state),
+            state_lock,
+        }
+    }
+
+    /// get the state name
+    pub fn name(&self) -> &'static str {
+        self.state.name()
+    }
+
+    /// get the state family name
+    pub fn family(&self) -> &'static str {
+        self.state.family()
+    }
+
+    /// get the state lock that the state belongs to
+    pub fn state_lock(&self) -> &Arc<StateLock<F>> {
+        &self.state_lock
+    }
+
+    pub fn as_dyn_state(&self) -> &dyn State {
+        self.state.as_dyn_state()
+    }
+
+    /// convert to a concrete state type
+    pub fn as_state<T: State>(&self) -> &T {
+        self.state.downcast()
+    }
+
+    /// convert to OwnedStateGuard
+    pub fn into_guard<T: State>(self) -> OwnedStateGuard<T, F> {
+        let _ = self.state.downcast::<T>(); // check type
+        OwnedStateGuard {
+            state: self,
+            _phantom: PhantomData,
+        }
+    }
+}
+
+/// state guard that owns the `StateLock`, it could be moved into other coroutines
+pub struct OwnedStateGuard<T: State, F = AnyFamily> {
+    state: OwnedRawState<F>,
+    _phantom: PhantomData<&'static T>,
+}
+
+impl<T: State, F> Debug for OwnedStateGuard<T, F> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "OwnedStateGuard{{ {}: {} }}",
+            self.state.family(),
+            self.state.name()
+        )
+    }
+}
+
+impl<T: State, F> OwnedStateGuard<T, F> {
+    /// get the state name
+    pub fn name(&self) -> &'static str {
+        self.state.name()
+    }
+    /// get the state family name
+    pub fn family(&self) -> &'static str {
+        self.state.family()
+    }
+
+    /// get the state lock that the state belongs to
+    pub fn state_lock(&self) -> &Arc<StateLock<F>> {
+        self.state.state_lock()
+    }
+}
+
+impl<T: State, F> Deref for OwnedStateGuard<T, F> {
+    type Target = T;
+    fn deref(&self) -> &Self::Target {
+        self.state.as_state()
+    }
+}
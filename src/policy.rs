@@ -0,0 +1,194 @@
+use std::cmp::Reverse;
+use std::time::{Duration, Instant};
+
+/// the state group that is waiting to be activated
+#[derive(Debug, Clone, Copy)]
+pub struct PendingGroup<'a> {
+    /// the state family of the state lock
+    pub state_family: &'a str,
+    /// the state name that the group is waiting for
+    pub state_name: &'a str,
+    /// number of waiters in the group
+    pub waiter_count: usize,
+    /// how long the oldest waiter in the group has waited
+    pub oldest_wait: Duration,
+    /// the time the state was released last time, `None` if never active
+    pub last_active: Option<Instant>,
+}
+
+/// policy that choose the next state group to activate
+///
+/// `StateLock` would call `pick_next` when the current state is released
+/// and there are more than one pending state groups.
+pub trait SchedulingPolicy: Send + Sync {
+    /// return the index of the next group in `groups`
+    /// `groups` are in the order of their first waiter arrived
+    /// `last_state` is the most recently released state, if any
+    fn pick_next(&self, last_state: Option<&str>, groups: &[PendingGroup]) -> usize;
+}
+
+/// the state whose first waiter arrived earliest goes first, this is the default policy
+#[derive(Debug, Default, Clone, Copy)]
+pub struct Fifo;
+
+impl SchedulingPolicy for Fifo {
+    fn pick_next(&self, _last_state: Option<&str>, _groups: &[PendingGroup]) -> usize {
+        0
+    }
+}
+
+/// the state with the most waiters goes first, ties are broken by arrival order
+///
+/// this would maximize the throughput, but small groups could starve under heavy load
+#[derive(Debug, Default, Clone, Copy)]
+pub struct LargestGroupFirst;
+
+impl SchedulingPolicy for LargestGroupFirst {
+    fn pick_next(&self, _last_state: Option<&str>, groups: &[PendingGroup]) -> usize {
+        groups
+            .iter()
+            .enumerate()
+            .min_by_key(|(i, g)| (Reverse(g.waiter_count), *i))
+            .map(|(i, _)| i)
+            .unwrap_or(0)
+    }
+}
+
+/// the state that has not been active for the longest time goes first
+/// states that are never active are preferred, ties are broken by arrival order
+#[derive(Debug, Default, Clone, Copy)]
+pub struct LeastRecentlyActive;
+
+impl SchedulingPolicy for LeastRecentlyActive {
+    fn pick_next(&self, _last_state: Option<&str>, groups: &[PendingGroup]) -> usize {
+        groups
+            .iter()
+            .enumerate()
+            .min_by_key(|(i, g)| (g.last_active, *i))
+            .map(|(i, _)| i)
+            .unwrap_or(0)
+    }
+}
+
+/// the state group that starts the order with the lowest total transition cost goes first
+///
+/// the total cost is the cost to serve all the pending groups one by one from the last state.
+/// the order is searched exhaustively for at most `MAX_EXHAUSTIVE_GROUPS` groups, for more
+/// groups the one with the lowest transition cost from the last state is picked.
+///
+/// the transition costs are declared for the state family by `#[transition_cost(...)]`
+/// or `TRANSITION_COST_REGISTRATION`. to bound the waiting time, a group that has waited
+/// for more than `max_wait` goes first, the longest waiting one first.
+#[derive(Debug, Clone, Copy)]
+pub struct LowestTransitionCost {
+    max_wait: Duration,
+}
+
+impl LowestTransitionCost {
+    /// the max number of the pending groups to search the lowest total cost order
+    pub const MAX_EXHAUSTIVE_GROUPS: usize = 10;
+
+    /// create the policy, the state family is the one of the state lock
+    pub fn new(max_wait: Duration) -> Self {
+        LowestTransitionCost { max_wait }
+    }
+}
+
+impl SchedulingPolicy for LowestTransitionCost {
+    fn pick_next(&self, last_state: Option<&str>, groups: &[PendingGroup]) -> usize {
+        let starving = groups
+            .iter()
+            .enumerate()
+            .filter(|(_, g)| g.oldest_wait >= self.max_wait)
+            .max_by_key(|(i, g)| (g.oldest_wait, Reverse(*i)));
+        if let Some((i, _)) = starving {
+            return i;
+        }
+
+        let cost = |from: &str, to: &PendingGroup| {
+            crate::registry::transition_cost(to.state_family, from, to.state_name)
+        };
+        // the first transition is free if there is no last state
+        let first_cost = groups
+            .iter()
+            .map(|g| last_state.map_or(0, |last| cost(last, g)))
+            .collect::<Vec<_>>();
+        if groups.len() > Self::MAX_EXHAUSTIVE_GROUPS {
+            let first = first_cost.iter().enumerate().min_by_key(|(i, c)| (**c, *i));
+            return first.map(|(i, _)| i).unwrap_or(0);
+        }
+
+        let costs = groups
+            .iter()
+            .map(|from| groups.iter().map(|to| cost(from.state_name, to)).collect())
+            .collect::<Vec<Vec<u64>>>();
+        let path_cost = lowest_path_cost(&costs);
+        let full = (1 << groups.len()) - 1;
+        let total = |i: usize| first_cost[i].saturating_add(path_cost[full][i]);
+        (0..groups.len())
+            .min_by_key(|&i| (total(i), i))
+            .unwrap_or(0)
+    }
+}
+
+/// `path[set][i]` is the lowest cost to serve all the groups in the set starting from group `i`
+fn lowest_path_cost(costs: &[Vec<u64>]) -> Vec<Vec<u64>> {
+    let n = costs.len();
+    let mut path = vec![vec![u64::MAX; n]; 1 << n];
+    for set in 1..path.len() {
+        for i in (0..n).filter(|i| set & (1 << i) != 0) {
+            let rest = set & !(1 << i);
+            path[set][i] = if rest == 0 {
+                0
+            } else {
+                let next = (0..n).filter(|j| rest & (1 << j) != 0);
+                let next = next.map(|j| costs[i][j].saturating_add(path[rest][j]));
+                next.min().unwrap_or(u64::MAX)
+            };
+        }
+    }
+    path
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn group(
+        state_name: &str,
+        waiter_count: usize,
+        last_active: Option<Instant>,
+    ) -> PendingGroup<'_> {
+        PendingGroup {
+            state_family: "PolicyTest",
+            state_name,
+            waiter_count,
+            oldest_wait: Duration::ZERO,
+            last_active,
+        }
+    }
+
+    #[test]
+    fn largest_group_first() {
+        let groups = [
+            group("A", 1, None),
+            group("B", 3, None),
+            group("C", 3, None),
+        ];
+        assert_eq!(Fifo.pick_next(None, &groups), 0);
+        assert_eq!(LargestGroupFirst.pick_next(None, &groups), 1);
+    }
+
+    #[test]
+    fn least_recently_active() {
+        let now = Instant::now();
+        let earlier = now - Duration::from_secs(1);
+        let groups = [
+            group("A", 1, Some(now)),
+            group("B", 1, Some(earlier)),
+            group("C", 1, None),
+        ];
+        assert_eq!(LeastRecentlyActive.pick_next(Some("A"), &groups), 2);
+        assert_eq!(LeastRecentlyActive.pick_next(Some("A"), &groups[..2]), 1);
+    }
+}
@@ -2,7 +2,8 @@ use once_cell::sync::Lazy;
 
 use crate::state::State;
 
-use std::collections::BTreeMap;
+use std::collections::{BTreeMap, BTreeSet};
+use std::io;
 
 // This is what we registered
 pub struct StateRegistration {
@@ -18,18 +19,81 @@ pub struct StateRegistration {
 #[linkme(crate = intertrait::linkme)]
 pub static STATE_REGISTRATION: [StateRegistration] = [..];
 
-type RegisteredState = BTreeMap<&'static str, fn() -> Box<dyn State>>;
+// the state that could fail to tear up, the error is returned to the waiters
+pub struct FallibleStateRegistration {
+    // state family name
+    pub state_family: &'static str,
+    // state name
+    pub state: &'static str,
+    // fallible tear up of the state
+    pub try_tear_up_fn: fn() -> io::Result<Box<dyn State>>,
+}
+
+#[intertrait::linkme::distributed_slice]
+#[linkme(crate = intertrait::linkme)]
+pub static FALLIBLE_STATE_REGISTRATION: [FallibleStateRegistration] = [..];
+
+// the cost of the transition between two states in the same family
+pub struct TransitionCostRegistration {
+    // state family name
+    pub state_family: &'static str,
+    // the state that is released
+    pub from: &'static str,
+    // the state that is going to be activated
+    pub to: &'static str,
+    // relative cost of the transition, like the time to tear down and tear up
+    pub cost: u64,
+}
+
+#[intertrait::linkme::distributed_slice]
+#[linkme(crate = intertrait::linkme)]
+pub static TRANSITION_COST_REGISTRATION: [TransitionCostRegistration] = [..];
+
+// two states in the same family that could be live at the same time
+pub struct CompatibilityRegistration {
+    // state family name
+    pub state_family: &'static str,
+    // state name
+    pub state: &'static str,
+    // the state that is compatible with `state`, the relation is symmetric
+    pub compatible: &'static str,
+}
+
+#[intertrait::linkme::distributed_slice]
+#[linkme(crate = intertrait::linkme)]
+pub static COMPATIBILITY_REGISTRATION: [CompatibilityRegistration] = [..];
+
+#[derive(Clone, Copy)]
+enum TearUpFn {
+    Infallible(fn() -> Box<dyn State>),
+    Fallible(fn() -> io::Result<Box<dyn State>>),
+}
+
+impl TearUpFn {
+    fn tear_up(self) -> io::Result<Box<dyn State>> {
+        match self {
+            TearUpFn::Infallible(tear_up) => Ok(tear_up()),
+            TearUpFn::Fallible(try_tear_up) => try_tear_up(),
+        }
+    }
+}
+
+type RegisteredState = BTreeMap<&'static str, TearUpFn>;
 type RegisteredStateSet = BTreeMap<&'static str, RegisteredState>;
 // state registration
 static REGISTERED_STATES: Lazy<RegisteredStateSet> = Lazy::new(|| {
+    let infallible = STATE_REGISTRATION.iter().map(|r| {
+        let tear_up = TearUpFn::Infallible(r.tear_up_fn);
+        (r.state_family, r.state, tear_up)
+    });
+    let fallible = FALLIBLE_STATE_REGISTRATION.iter().map(|r| {
+        let tear_up = TearUpFn::Fallible(r.try_tear_up_fn);
+        (r.state_family, r.state, tear_up)
+    });
     let mut map = BTreeMap::new();
-    for registered in STATE_REGISTRATION {
-        let state_map = map
-            .entry(registered.state_family)
-            .or_insert_with(BTreeMap::new);
-        state_map
-            .entry(registered.state)
-            .or_insert(registered.tear_up_fn);
+    for (state_family, state, tear_up) in infallible.chain(fallible) {
+        let state_map = map.entry(state_family).or_insert_with(BTreeMap::new);
+        state_map.entry(state).or_insert(tear_up);
     }
     map
 });
@@ -45,8 +109,63 @@ pub fn state_names(state_family: &str) -> impl Iterator<Item = &'static str> + '
     state_family.keys().copied()
 }
 
-pub fn tear_up_registered_state(state_family: &str, name: &str) -> Option<Box<dyn State>> {
+pub fn tear_up_registered_state(state_family: &str, name: &str) -> io::Result<Box<dyn State>> {
     let state_family = get_state_family(state_family);
-    let tear_up = state_family.get(&name)?;
-    Some(tear_up())
+    let tear_up = state_family.get(&name).ok_or_else(|| {
+        let err_msg = format!("state {name} is not registered");
+        io::Error::new(io::ErrorKind::NotFound, err_msg)
+    })?;
+    tear_up.tear_up()
+}
+
+/// the cost of undeclared transitions
+pub const DEFAULT_TRANSITION_COST: u64 = 1;
+
+type StatePair = (&'static str, &'static str, &'static str);
+// transition cost registration, key is (state_family, from, to)
+static REGISTERED_COSTS: Lazy<BTreeMap<StatePair, u64>> = Lazy::new(|| {
+    let mut map = BTreeMap::new();
+    for registered in TRANSITION_COST_REGISTRATION {
+        let key = (registered.state_family, registered.from, registered.to);
+        map.entry(key).or_insert(registered.cost);
+    }
+    map
+});
+
+/// the transition cost from one state to another in the family
+/// if only the reverse transition is declared, it's used for both directions
+pub fn transition_cost(state_family: &str, from: &str, to: &str) -> u64 {
+    if from == to {
+        return 0;
+    }
+    let cost = |from, to| REGISTERED_COSTS.get(&(state_family, from, to)).copied();
+    cost(from, to)
+        .or_else(|| cost(to, from))
+        .unwrap_or(DEFAULT_TRANSITION_COST)
+}
+
+// compatibility registration, key is (state_family, state, compatible)
+static REGISTERED_COMPATIBILITY: Lazy<BTreeSet<StatePair>> = Lazy::new(|| {
+    COMPATIBILITY_REGISTRATION
+        .iter()
+        .map(|r| (r.state_family, r.state, r.compatible))
+        .collect()
+});
+
+/// check if the two states in the family could be live at the same time
+/// a state is never compatible with itself, the waiters just share it
+pub fn is_compatible(state_family: &str, a: &str, b: &str) -> bool {
+    if a == b {
+        return false;
+    }
+    let compatible = |a, b| REGISTERED_COMPATIBILITY.contains(&(state_family, a, b));
+    compatible(a, b) || compatible(b, a)
+}
+
+/// the registered family name that has the family id
+pub fn family_name(id: u64) -> Option<&'static str> {
+    REGISTERED_STATES
+        .keys()
+        .copied()
+        .find(|name| crate::family::family_id(name) == id)
 }
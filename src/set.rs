@@ -0,0 +1,51 @@
+use crate::state::RawState;
+use crate::StateLock;
+
+use std::io;
+
+/// lock states across several `StateLock`s
+///
+/// the states are acquired one by one in a global order of the state locks,
+/// so two jobs requesting opposite combinations of states could not deadlock.
+/// the acquisition is not atomic, the states acquired earlier are held
+/// while waiting for the later ones.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct StateLockSet;
+
+impl StateLockSet {
+    /// lock all the requested states, the states are returned in the request order
+    ///
+    /// if any of the states failed to lock, the states acquired so far are released
+    /// and the error is returned.
+    /// requesting different states of the same state lock is an `InvalidInput` error.
+    /// the state locks are all `StateLock`s, or all typed with the same family
+    pub fn lock_all<'a, F>(requests: &[(&'a StateLock<F>, &str)]) -> io::Result<Vec<RawState<'a>>> {
+        // the address is stable while the state lock is borrowed
+        let lock_order = |lock: &StateLock<F>| lock as *const StateLock<F> as usize;
+        let mut order = (0..requests.len()).collect::<Vec<_>>();
+        order.sort_by_key(|&i| lock_order(requests[i].0));
+
+        let mut states: Vec<Option<RawState<'a>>> = vec![None; requests.len()];
+        let mut last: Option<usize> = None;
+        for i in order {
+            let (state_lock, state_name) = requests[i];
+            // the same state lock is requested again, share the acquired state
+            if let Some(j) = last.filter(|&j| std::ptr::eq(requests[j].0, state_lock)) {
+                if requests[j].1 != state_name {
+                    let err_msg = format!(
+                        "request both {} and {state_name} state of {} family",
+                        requests[j].1,
+                        state_lock.state_family()
+                    );
+                    return Err(io::Error::new(io::ErrorKind::InvalidInput, err_msg));
+                }
+                states[i] = states[j].clone();
+                continue;
+            }
+            // the acquired states are dropped on error
+            states[i] = Some(state_lock.lock_by_state_name(state_name)?);
+            last = Some(i);
+        }
+        Ok(states.into_iter().map(|s| s.unwrap()).collect())
+    }
+}
@@ -1,12 +1,24 @@
 use intertrait::CastFrom;
 
+use crate::family::{AnyFamily, Family, MemberOf};
+use crate::key::{StateId, StateKey};
+use crate::lock::StateOwner;
 use crate::registry::tear_up_registered_state;
 use crate::StateLock;
 
+use may::sync::Mutex;
+
+use std::any::Any;
+use std::cell::UnsafeCell;
+use std::collections::HashMap;
 use std::fmt::{self, Debug};
+use std::io;
 use std::marker::PhantomData;
-use std::ops::Deref;
+use std::ops::{Deref, DerefMut};
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
 use std::sync::Arc;
+use std::time::Instant;
 
 /// any type that impl `State` can be used by `StateLock`
 ///
@@ -29,75 +41,273 @@ pub trait State: CastFrom + Sync {
     where
         Self: Sized;
 
+    /// fallible tear up, the error would be returned to all the waiters of the state
+    /// by default it just calls `tear_up`
+    fn try_tear_up() -> io::Result<Self>
+    where
+        Self: Sized,
+    {
+        Ok(Self::tear_up())
+    }
+
     /// tear down the state, just drop the state
     fn tear_down(&mut self) {
         trace!("{} state tear down", self.name());
     }
+
+    /// the relative size of the state, used by `CacheCapacity::Weighted`
+    fn size_hint(&self) -> usize {
+        1
+    }
+
+    /// convert this released state into the `next` state in place
+    ///
+    /// this is tried before the normal tear down and tear up, return `None` to fall back.
+    /// when a new state is returned, this state is dropped without calling `tear_down`,
+    /// so the resources moved into the new state would not be released.
+    fn transition_into(&mut self, next: &str) -> Option<Box<dyn State>> {
+        let _ = next;
+        None
+    }
+}
+
+static NEXT_HOLDER: AtomicUsize = AtomicUsize::new(0);
+
+may::coroutine_local!(static HOLDER: usize = NEXT_HOLDER.fetch_add(1, Ordering::Relaxed));
+
+/// unique id of the current coroutine or thread that holds the state guards
+pub(crate) fn current_holder() -> usize {
+    HOLDER.with(|holder| *holder)
+}
+
+/// the message of the panic payload
+pub(crate) fn panic_message(payload: &(dyn Any + Send)) -> &str {
+    if let Some(msg) = payload.downcast_ref::<&str>() {
+        msg
+    } else if let Some(msg) = payload.downcast_ref::<String>() {
+        msg
+    } else {
+        "unknown panic"
+    }
 }
 
 /// internal state wrapper that would call tear_down automatically when dropped
 pub(crate) struct StateWrapper<'a> {
     // State lock hold the state, it's safe to have the reference
-    state_lock: &'a StateLock,
-    // State is `Sync` but not `Send`
-    state: Option<Box<dyn State>>,
+    state_lock: &'a dyn StateOwner,
+    // State is `Sync` but not `Send`, it's only mutated by the exclusive guard
+    state: UnsafeCell<Option<Box<dyn State>>>,
+    // cached state name, so the state lock never touch the state held exclusively
+    name: &'static str,
+    family: &'static str,
+    // the key of a keyed state
+    key: Option<StateKey>,
+    // no one could join the state when it's held exclusively, set under the state lock
+    exclusive: AtomicBool,
+    // the time the state is activated
+    activated_at: Instant,
+    // number of the guards held by each coroutine, used to detect deadlock
+    holders: Mutex<HashMap<usize, usize>>,
+    // a holder panicked with the guard
+    poisoned: AtomicBool,
 }
 
 unsafe impl Send for StateWrapper<'_> {}
+// the state is only accessed mutably when there is no other guard
+unsafe impl Sync for StateWrapper<'_> {}
 
 impl StateWrapper<'_> {
-    pub(crate) fn new(state_lock: &StateLock, state: Option<Box<dyn State>>) -> Self {
+    pub(crate) fn new(
+        state_lock: &dyn StateOwner,
+        state: Box<dyn State>,
+        key: Option<StateKey>,
+    ) -> Self {
+        let wrapper = StateWrapper {
+            state_lock,
+            name: state.name(),
+            family: state.family(),
+            state: UnsafeCell::new(Some(state)),
+            key,
+            exclusive: AtomicBool::new(false),
+            activated_at: Instant::now(),
+            holders: Mutex::new(HashMap::new()),
+            poisoned: AtomicBool::new(false),
+        };
         // it's safe to eliminate the life time here, basically they are equal
-        unsafe { std::mem::transmute(StateWrapper { state_lock, state }) }
+        unsafe { std::mem::transmute(wrapper) }
     }
 
-    pub(crate) fn new_from_name(state_lock: &StateLock, name: &str) -> Option<Self> {
-        let state = if let Some(custom_tear_up) = state_lock.custom_tear_up.as_ref() {
-            Some(custom_tear_up(name))
-        } else {
-            Some(tear_up_registered_state(state_lock.state_family(), name)?)
+    /// tear up the state, keyed states are always tear up with their key
+    pub(crate) fn new_from_id<F>(state_lock: &StateLock<F>, id: &StateId) -> io::Result<Self> {
+        let tear_up = || {
+            if let Some(key) = id.key.as_ref() {
+                key.tear_up()
+            } else if let Some(custom_tear_up) = state_lock.custom_tear_up.as_ref() {
+                custom_tear_up(&id.name)
+            } else {
+                tear_up_registered_state(state_lock.state_family(), &id.name)
+            }
         };
-        // it's safe to eliminate the life time here, basically they are equal
-        unsafe { std::mem::transmute(StateWrapper { state_lock, state }) }
+        // a panic in the tear up fails the waiters like an error
+        let tear_up = || {
+            panic::catch_unwind(AssertUnwindSafe(tear_up)).unwrap_or_else(|e| {
+                let err_msg = format!("{id} state tear up panicked: {}", panic_message(&*e));
+                Err(io::Error::other(err_msg))
+            })
+        };
+        let state = in_span!(
+            tear_up,
+            "state_lock.tear_up",
+            family = state_lock.state_family(),
+            state = %id,
+        )?;
+        Ok(StateWrapper::new(state_lock, state, id.key.clone()))
     }
 
     /// return the state name
     pub(crate) fn name(&self) -> &'static str {
-        self.state.as_ref().unwrap().name()
+        self.name
     }
 
     /// return the state family name
     pub(crate) fn family(&self) -> &'static str {
-        self.state.as_ref().unwrap().family()
+        self.family
+    }
+
+    /// check if the coroutine holds a guard of the state
+    pub(crate) fn is_held_by(&self, holder: usize) -> bool {
+        self.holders.lock().unwrap().contains_key(&holder)
+    }
+
+    /// number of the guards of the state held by the coroutine
+    pub(crate) fn held_count(&self, holder: usize) -> usize {
+        let holders = self.holders.lock().unwrap();
+        holders.get(&holder).copied().unwrap_or_default()
+    }
+
+    /// check if they are the same state wrapper
+    pub(crate) fn is_same(&self, other: &StateWrapper) -> bool {
+        std::ptr::eq(
+            self as *const _ as *const u8,
+            other as *const _ as *const u8,
+        )
+    }
+
+    /// check if the state is held by an exclusive guard
+    pub(crate) fn is_exclusive(&self) -> bool {
+        self.exclusive.load(Ordering::Acquire)
+    }
+
+    pub(crate) fn set_exclusive(&self, exclusive: bool) {
+        self.exclusive.store(exclusive, Ordering::Release)
+    }
+
+    /// check if the state has the identity
+    pub(crate) fn is(&self, id: &StateId) -> bool {
+        id.is(self.name(), self.key.as_ref())
     }
 
     /// downcast to a concrete state type
     pub(crate) fn downcast<T: State>(&self) -> &T {
-        let any = match self.state.as_ref() {
-            Some(s) => s.as_ref().ref_any(),
-            None => panic!("no state found"),
-        };
+        let any = self.as_dyn_state().ref_any();
         any.downcast_ref::<T>().expect("wrong state cast")
     }
 
-    fn as_dyn_state(&self) -> &dyn State {
-        self.state.as_ref().unwrap().as_ref()
+    /// the caller must be the only one that could access the state to mutate it
+    fn state_ptr(&self) -> *mut Option<Box<dyn State>> {
+        self.state.get()
+    }
+
+    pub(crate) fn as_dyn_state(&self) -> &dyn State {
+        // the state is never mutated when it's shared
+        let state = unsafe { &*self.state.get() };
+        state.as_deref().expect("no state found")
     }
 }
 
 impl Drop for StateWrapper<'_> {
     fn drop(&mut self) {
-        let state = self.state.take().unwrap();
-        self.state_lock.save_last_state(state);
+        let state = self.state.get_mut().take().unwrap();
+        let active = self.activated_at.elapsed();
+        let poisoned = *self.poisoned.get_mut();
+        self.state_lock
+            .save_last_state(state, self.key.take(), active, poisoned);
         self.state_lock.wakeup_next_group();
     }
 }
 
-/// general state that can access the shared state
-pub struct RawState<'a> {
+/// a state reference of a guard, the coroutine that acquires it is the holder
+///
+/// the holder is only tracked when the deadlock detection is enabled,
+/// a guard moved to another coroutine is still held by the one that acquired it
+pub(crate) struct Held<'a> {
     // we use `Arc` to track the state references
     // when all `StateWrapper`s are dropped, the state would be tear_down
     state: Arc<StateWrapper<'a>>,
+    // `None` if the holder is not tracked
+    holder: Option<usize>,
+}
+
+impl<'a> Held<'a> {
+    pub(crate) fn new(state: Arc<StateWrapper<'a>>) -> Self {
+        if !state.state_lock.detects_deadlock() {
+            return Held::untracked(state);
+        }
+        let holder = current_holder();
+        *state.holders.lock().unwrap().entry(holder).or_default() += 1;
+        Held {
+            state,
+            holder: Some(holder),
+        }
+    }
+
+    /// the holder is not tracked, so it's never treated as a deadlock
+    pub(crate) fn untracked(state: Arc<StateWrapper<'a>>) -> Self {
+        Held {
+            state,
+            holder: None,
+        }
+    }
+}
+
+impl Clone for Held<'_> {
+    fn clone(&self) -> Self {
+        match self.holder {
+            Some(_) => Held::new(self.state.clone()),
+            None => Held::untracked(self.state.clone()),
+        }
+    }
+}
+
+impl<'a> Deref for Held<'a> {
+    type Target = StateWrapper<'a>;
+    fn deref(&self) -> &Self::Target {
+        &self.state
+    }
+}
+
+impl Drop for Held<'_> {
+    fn drop(&mut self) {
+        if std::thread::panicking() {
+            self.state.poisoned.store(true, Ordering::Relaxed);
+        }
+        let Some(holder) = self.holder else {
+            return;
+        };
+        let mut holders = self.state.holders.lock().unwrap();
+        if let Some(count) = holders.get_mut(&holder) {
+            *count -= 1;
+            if *count == 0 {
+                holders.remove(&holder);
+            }
+        }
+    }
+}
+
+/// general state that can access the shared state
+#[derive(Clone)]
+pub struct RawState<'a> {
+    state: Held<'a>,
 }
 
 // unsafe impl<'a> Sync for RawState<'a> {}
@@ -115,7 +325,9 @@ impl Debug for RawState<'_> {
 
 impl<'a> RawState<'a> {
     pub(crate) fn new(state: Arc<StateWrapper<'a>>) -> Self {
-        RawState { state }
+        RawState {
+            state: Held::new(state),
+        }
     }
 
     /// get the state name
@@ -137,27 +349,69 @@ impl<'a> RawState<'a> {
         self.state.downcast()
     }
 
+    /// release the state and lock for another state by it's name
+    ///
+    /// other callers could not take the next turn of the state in between,
+    /// the current state is released even if it fails
+    pub fn switch_to(self, state_name: &str) -> io::Result<RawState<'a>> {
+        self.state.state_lock.switch_state(self, state_name)
+    }
+
+    /// project the state to a part of it, the state stays active with the mapped guard
+    pub fn map<U: ?Sized, F>(state: Self, f: F) -> MappedStateGuard<'a, U>
+    where
+        F: FnOnce(&dyn State) -> &U,
+    {
+        let value = f(state.as_dyn_state()) as *const U;
+        MappedStateGuard {
+            state: state.state,
+            value,
+        }
+    }
+
+    pub(crate) fn wrapper(&self) -> &StateWrapper<'a> {
+        &self.state
+    }
+
     /// convert to StateGuard
     pub fn into_guard<T: State>(self) -> StateGuard<'a, T> {
+        self.into_family_guard()
+    }
+
+    /// convert to StateGuard of the family
+    pub(crate) fn into_family_guard<T: State, F>(self) -> StateGuard<'a, T, F> {
         let _ = self.state.downcast::<T>(); // check type
         StateGuard {
             state: self.state,
             _phantom: PhantomData,
+            _family: PhantomData,
+        }
+    }
+
+    /// convert the exclusively held state to StateGuardMut
+    pub(crate) fn into_guard_mut<T: State, F>(self) -> StateGuardMut<'a, T, F> {
+        let _ = self.state.downcast::<T>(); // check type
+        debug_assert!(self.state.is_exclusive());
+        StateGuardMut {
+            state: self.state,
+            _phantom: PhantomData,
+            _family: PhantomData,
         }
     }
 }
 
 /// state guard that can access the shared state with concrete type
-pub struct StateGuard<'a, T: State> {
-    // we use `Arc` to track the state references
-    // when all `StateWrapper`s are dropped, the state would be tear_down
-    state: Arc<StateWrapper<'a>>,
+///
+/// the guard of a typed `StateLock<F>` is typed with the family
+pub struct StateGuard<'a, T: State, F = AnyFamily> {
+    state: Held<'a>,
     _phantom: PhantomData<&'a T>,
+    _family: PhantomData<fn() -> F>,
 }
 
 // unsafe impl<'a, T: State> Sync for StateGuard<'a, T> {}
 
-impl<T: State> Debug for StateGuard<'_, T> {
+impl<T: State, F> Debug for StateGuard<'_, T, F> {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         write!(
             f,
@@ -168,7 +422,23 @@ impl<T: State> Debug for StateGuard<'_, T> {
     }
 }
 
-impl<T: State> StateGuard<'_, T> {
+impl<'a, T: State> StateGuard<'a, T> {
+    /// release the state and lock for the `U` state in one step
+    /// other callers could not take the next turn of the `U` state in between
+    pub fn switch_to<U: State>(self) -> io::Result<StateGuard<'a, U>> {
+        self.switch_guard()
+    }
+}
+
+impl<'a, T: State, F: Family> StateGuard<'a, T, F> {
+    /// release the state and lock for the `U` state of the family in one step
+    /// other callers could not take the next turn of the `U` state in between
+    pub fn switch_to<U: MemberOf<F>>(self) -> io::Result<StateGuard<'a, U, F>> {
+        self.switch_guard()
+    }
+}
+
+impl<'a, T: State, F> StateGuard<'a, T, F> {
     /// get the state name
     pub fn name(&self) -> &'static str {
         self.state.name()
@@ -177,11 +447,159 @@ impl<T: State> StateGuard<'_, T> {
     pub fn family(&self) -> &'static str {
         self.state.family()
     }
+
+    fn switch_guard<U: State>(self) -> io::Result<StateGuard<'a, U, F>> {
+        let state = RawState { state: self.state };
+        state
+            .switch_to(U::state_name())
+            .map(|s| s.into_family_guard())
+    }
+
+    /// project the state to a part of it, like a field of the state
+    /// the state stays active as long as the mapped guard exists
+    pub fn map<U: ?Sized, M>(guard: Self, f: M) -> MappedStateGuard<'a, U>
+    where
+        M: FnOnce(&T) -> &U,
+    {
+        let value = f(guard.state.downcast()) as *const U;
+        MappedStateGuard {
+            state: guard.state,
+            value,
+        }
+    }
+
+    /// upgrade to the exclusive guard without any state transition
+    /// fail and return the guard back if there are other guards of the state
+    pub fn try_upgrade(self) -> Result<StateGuardMut<'a, T, F>, Self> {
+        if !self.state.state_lock.try_set_exclusive(&self.state.state) {
+            return Err(self);
+        }
+        Ok(StateGuardMut {
+            state: self.state,
+            _phantom: PhantomData,
+            _family: PhantomData,
+        })
+    }
 }
 
-impl<T: State> Deref for StateGuard<'_, T> {
+impl<T: State, F> Deref for StateGuard<'_, T, F> {
     type Target = T;
     fn deref(&self) -> &Self::Target {
         self.state.downcast()
     }
 }
+
+/// state guard that can access a part of the shared state
+pub struct MappedStateGuard<'a, U: ?Sized> {
+    state: Held<'a>,
+    // points into the state, which is kept alive by the state ref
+    value: *const U,
+}
+
+// it's just a shared reference to the part of the state
+unsafe impl<U: ?Sized + Sync> Send for MappedStateGuard<'_, U> {}
+unsafe impl<U: ?Sized + Sync> Sync for MappedStateGuard<'_, U> {}
+
+impl<U: ?Sized> Debug for MappedStateGuard<'_, U> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "MappedStateGuard{{ {}: {} }}",
+            self.state.family(),
+            self.state.name()
+        )
+    }
+}
+
+impl<'a, U: ?Sized> MappedStateGuard<'a, U> {
+    /// get the state name
+    pub fn name(&self) -> &'static str {
+        self.state.name()
+    }
+    /// get the state family name
+    pub fn family(&self) -> &'static str {
+        self.state.family()
+    }
+
+    /// project the mapped part further
+    pub fn map<V: ?Sized, F>(guard: Self, f: F) -> MappedStateGuard<'a, V>
+    where
+        F: FnOnce(&U) -> &V,
+    {
+        let value = f(&guard) as *const V;
+        MappedStateGuard {
+            state: guard.state,
+            value,
+        }
+    }
+}
+
+impl<U: ?Sized> Deref for MappedStateGuard<'_, U> {
+    type Target = U;
+    fn deref(&self) -> &Self::Target {
+        // the state is never mutated when it's shared
+        unsafe { &*self.value }
+    }
+}
+
+/// exclusive state guard that can mutate the state with concrete type
+///
+/// no other guard could be granted for the state until it's dropped or downgraded
+pub struct StateGuardMut<'a, T: State, F = AnyFamily> {
+    // it's the only reference to the state
+    state: Held<'a>,
+    _phantom: PhantomData<&'a mut T>,
+    _family: PhantomData<fn() -> F>,
+}
+
+impl<T: State, F> Debug for StateGuardMut<'_, T, F> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "StateGuardMut{{ {}: {} }}",
+            self.state.family(),
+            self.state.name()
+        )
+    }
+}
+
+impl<'a, T: State, F> StateGuardMut<'a, T, F> {
+    /// get the state name
+    pub fn name(&self) -> &'static str {
+        self.state.name()
+    }
+    /// get the state family name
+    pub fn family(&self) -> &'static str {
+        self.state.family()
+    }
+
+    /// downgrade to the shared guard without any state transition
+    /// the waiters for the same state could join the state after that
+    pub fn downgrade(self) -> StateGuard<'a, T, F> {
+        self.state.state_lock.clear_exclusive(&self.state);
+        StateGuard {
+            state: self.state,
+            _phantom: PhantomData,
+            _family: PhantomData,
+        }
+    }
+}
+
+impl<T: State, F> Deref for StateGuardMut<'_, T, F> {
+    type Target = T;
+    fn deref(&self) -> &Self::Target {
+        self.state.downcast()
+    }
+}
+
+impl<T: State, F> DerefMut for StateGuardMut<'_, T, F> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        // no one else could access the state when it's held exclusively
+        let state = unsafe { &mut *self.state.state_ptr() };
+        let state = state.as_deref_mut().expect("no state found");
+        state
+            .mut_any()
+            .downcast_mut::<T>()
+            .expect("wrong state cast")
+    }
+}
@@ -0,0 +1,181 @@
+use crate::waiter::WaiterGroup;
+
+use std::collections::{BTreeMap, HashMap};
+use std::time::{Duration, Instant};
+
+const BUCKETS: usize = 32;
+
+/// histogram of durations, the buckets are in power of 2 microseconds
+#[derive(Debug, Clone, Default)]
+pub struct Histogram {
+    count: u64,
+    sum: Duration,
+    max: Duration,
+    // bucket i counts the durations less than 2^i us, the last one counts the rest
+    buckets: [u64; BUCKETS],
+}
+
+impl Histogram {
+    pub(crate) fn record(&mut self, duration: Duration) {
+        let us = duration.as_micros() as u64;
+        let index = (u64::BITS - us.leading_zeros()) as usize;
+        self.buckets[index.min(BUCKETS - 1)] += 1;
+        self.count += 1;
+        self.sum += duration;
+        self.max = self.max.max(duration);
+    }
+
+    fn merge(&mut self, other: &Histogram) {
+        self.count += other.count;
+        self.sum += other.sum;
+        self.max = self.max.max(other.max);
+        for (a, b) in self.buckets.iter_mut().zip(other.buckets.iter()) {
+            *a += b;
+        }
+    }
+
+    /// number of the recorded durations
+    pub fn count(&self) -> u64 {
+        self.count
+    }
+
+    /// total of the recorded durations
+    pub fn sum(&self) -> Duration {
+        self.sum
+    }
+
+    /// the longest recorded duration
+    pub fn max(&self) -> Duration {
+        self.max
+    }
+
+    /// the average of the recorded durations
+    pub fn mean(&self) -> Duration {
+        match self.count {
+            0 => Duration::ZERO,
+            n => Duration::from_nanos((self.sum.as_nanos() / n as u128) as u64),
+        }
+    }
+
+    /// the upper bound of the bucket where the percentile falls in, `p` is in `0.0..=1.0`
+    pub fn percentile(&self, p: f64) -> Duration {
+        let target = (self.count as f64 * p.clamp(0.0, 1.0)).ceil() as u64;
+        let mut seen = 0;
+        for (upper, count) in self.buckets() {
+            seen += count;
+            if seen >= target {
+                return upper.min(self.max);
+            }
+        }
+        self.max
+    }
+
+    /// the non empty buckets, in the form of (exclusive upper bound, count)
+    pub fn buckets(&self) -> impl Iterator<Item = (Duration, u64)> + '_ {
+        self.buckets
+            .iter()
+            .enumerate()
+            .filter(|(_, count)| **count > 0)
+            .map(|(i, count)| {
+                let upper = match i {
+                    i if i == BUCKETS - 1 => Duration::MAX,
+                    i => Duration::from_micros(1 << i),
+                };
+                (upper, *count)
+            })
+    }
+}
+
+/// statistics of a state
+#[derive(Debug, Clone, Default)]
+pub struct StateStats {
+    /// number of the successful locks of the state
+    pub acquisitions: u64,
+    /// number of the times that the state is activated
+    pub transitions_in: u64,
+    /// number of the times that the state is released
+    pub transitions_out: u64,
+    /// number of the activations that reuse the cached state
+    pub cache_reuses: u64,
+    /// the time the waiters waited for the state, joining a live state is not counted
+    pub wait_time: Histogram,
+    /// the time to tear up the state
+    pub tear_up_time: Histogram,
+    /// the time to tear down the state
+    pub tear_down_time: Histogram,
+    /// total time the state was active, the current activation is not counted
+    pub active_time: Duration,
+}
+
+/// snapshot of the statistics of a `StateLock`
+#[derive(Debug, Clone, Default)]
+pub struct StateLockStats {
+    /// statistics of the states that have been used
+    pub states: BTreeMap<&'static str, StateStats>,
+}
+
+impl StateLockStats {
+    /// statistics of the state
+    pub fn get(&self, state_name: &str) -> Option<&StateStats> {
+        self.states.get(state_name)
+    }
+}
+
+/// statistics collected under the state lock
+#[derive(Default)]
+pub(crate) struct Stats {
+    states: HashMap<&'static str, StateStats>,
+}
+
+impl Stats {
+    fn state(&mut self, name: &'static str) -> &mut StateStats {
+        self.states.entry(name).or_default()
+    }
+
+    pub(crate) fn record_acquire(&mut self, name: &'static str, count: usize) {
+        self.state(name).acquisitions += count as u64;
+    }
+
+    /// the waiters in the group get the state
+    pub(crate) fn record_wakeup(&mut self, name: &'static str, waiters: &WaiterGroup) {
+        let now = Instant::now();
+        let stats = self.state(name);
+        stats.acquisitions += waiters.len() as u64;
+        for since in waiters.queued_times() {
+            stats.wait_time.record(now.saturating_duration_since(since));
+        }
+    }
+
+    pub(crate) fn record_activate(&mut self, name: &'static str, cache_reused: bool) {
+        let stats = self.state(name);
+        stats.transitions_in += 1;
+        stats.cache_reuses += cache_reused as u64;
+    }
+
+    pub(crate) fn record_tear_up(&mut self, name: &'static str, duration: Duration) {
+        self.state(name).tear_up_time.record(duration);
+    }
+
+    pub(crate) fn record_release(&mut self, name: &'static str, active: Duration) {
+        let stats = self.state(name);
+        stats.transitions_out += 1;
+        stats.active_time += active;
+    }
+
+    /// take a snapshot, the tear down time is tracked by the state cache
+    pub(crate) fn snapshot(
+        &self,
+        tear_down_time: &HashMap<&'static str, Histogram>,
+    ) -> StateLockStats {
+        let mut states = self
+            .states
+            .iter()
+            .map(|(name, stats)| (*name, stats.clone()))
+            .collect::<BTreeMap<_, _>>();
+        for (name, histogram) in tear_down_time {
+            let stats = states.entry(*name).or_default();
+            stats.tear_down_time.merge(histogram);
+        }
+        StateLockStats { states }
+    }
+}
@@ -0,0 +1,153 @@
+use may::sync::Mutex;
+use may_waiter::{TokenWaiter, ID};
+
+use crate::key::StateId;
+use crate::state::StateWrapper;
+
+use std::fmt::{self, Debug};
+use std::io;
+use std::sync::Arc;
+use std::task::Waker;
+use std::time::Instant;
+
+/// the response that a waiter would receive, error if the state failed to tear up
+pub(crate) type WaiterRsp = io::Result<Arc<StateWrapper<'static>>>;
+
+/// `io::Error` is not `Clone`, so we make a copy for each waiter
+pub(crate) fn copy_error(err: &io::Error) -> io::Error {
+    io::Error::new(err.kind(), err.to_string())
+}
+
+/// async waiter slot, the response is stored here and the waker is notified
+#[derive(Default)]
+pub(crate) struct AsyncWaiter {
+    inner: Mutex<AsyncWaiterInner>,
+}
+
+#[derive(Default)]
+struct AsyncWaiterInner {
+    rsp: Option<WaiterRsp>,
+    waker: Option<Waker>,
+}
+
+impl AsyncWaiter {
+    /// take the response if it's ready, or register the waker
+    pub(crate) fn poll_rsp(&self, waker: &Waker) -> Option<WaiterRsp> {
+        let mut inner = self.inner.lock().unwrap();
+        let rsp = inner.rsp.take();
+        if rsp.is_none() {
+            inner.waker = Some(waker.clone());
+        }
+        rsp
+    }
+
+    fn set_rsp(&self, rsp: WaiterRsp) {
+        let waker = {
+            let mut inner = self.inner.lock().unwrap();
+            inner.rsp = Some(rsp);
+            inner.waker.take()
+        };
+        if let Some(waker) = waker {
+            waker.wake();
+        }
+    }
+}
+
+/// a waiter in the waiter queue, could be a blocking or an async one
+pub(crate) enum Waiter {
+    Token(ID),
+    Async(Arc<AsyncWaiter>),
+}
+
+impl Waiter {
+    /// send the response to the waiter
+    pub(crate) fn set_rsp(self, rsp: WaiterRsp) {
+        match self {
+            Waiter::Token(id) => TokenWaiter::<WaiterRsp>::set_rsp(id, rsp),
+            Waiter::Async(waiter) => waiter.set_rsp(rsp),
+        }
+    }
+}
+
+impl PartialEq for Waiter {
+    fn eq(&self, other: &Self) -> bool {
+        match (self, other) {
+            (Waiter::Token(a), Waiter::Token(b)) => a == b,
+            (Waiter::Async(a), Waiter::Async(b)) => Arc::ptr_eq(a, b),
+            _ => false,
+        }
+    }
+}
+
+impl Debug for Waiter {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Waiter::Token(id) => write!(f, "{id:?}"),
+            Waiter::Async(waiter) => write!(f, "Async({:p})", Arc::as_ptr(waiter)),
+        }
+    }
+}
+
+/// all the waiters that are waiting for the same state, in queued order
+#[derive(Default)]
+pub(crate) struct WaiterGroup {
+    // the waiter and the time it's queued
+    waiters: Vec<(Waiter, Instant)>,
+}
+
+impl WaiterGroup {
+    pub(crate) fn push(&mut self, waiter: Waiter) {
+        self.waiters.push((waiter, Instant::now()));
+    }
+
+    /// remove the waiter from the group, return false if it's not in the group
+    pub(crate) fn remove(&mut self, waiter: &Waiter) -> bool {
+        match self.waiters.iter().position(|(w, _)| w == waiter) {
+            Some(pos) => {
+                self.waiters.remove(pos);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// split the first waiter out of the group
+    pub(crate) fn split_first(&mut self) -> WaiterGroup {
+        let waiters = if self.waiters.is_empty() {
+            Vec::new()
+        } else {
+            vec![self.waiters.remove(0)]
+        };
+        WaiterGroup { waiters }
+    }
+
+    pub(crate) fn len(&self) -> usize {
+        self.waiters.len()
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        self.waiters.is_empty()
+    }
+
+    /// the time each waiter is queued
+    pub(crate) fn queued_times(&self) -> impl Iterator<Item = Instant> + '_ {
+        self.waiters.iter().map(|(_, since)| *since)
+    }
+
+    /// the time the oldest waiter is queued
+    pub(crate) fn oldest(&self) -> Option<Instant> {
+        self.waiters.first().map(|(_, since)| *since)
+    }
+
+    /// send the response to all the waiters in the group
+    pub(crate) fn wakeup(self, id: &StateId, rsp: &WaiterRsp) {
+        for (waiter, _) in self.waiters {
+            trace!("wakeup {id} state, waiter {waiter:?}");
+            let rsp = match rsp {
+                Ok(state) => Ok(state.clone()),
+                Err(e) => Err(copy_error(e)),
+            };
+            waiter.set_rsp(rsp);
+        }
+    }
+}
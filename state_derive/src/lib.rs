@@ -1,10 +1,20 @@
 use proc_macro::TokenStream;
-use quote::quote;
+use quote::{format_ident, quote};
 use syn::parse::{Error, ParseStream, Result};
-use syn::{parse_macro_input, parse_quote, Attribute, Path, Token};
+use syn::punctuated::Punctuated;
+use syn::{parse_macro_input, parse_quote, Attribute, MetaNameValue, Path, Token};
 
 /// Derive macro generating an impl of the trait `state_lock::State`
-#[proc_macro_derive(State, attributes(family, state_lock))]
+///
+/// `#[transition_cost(B = 1, C = 10)]` declares the transition costs to other states
+/// `#[compatible_with(B, C)]` declares the states that could be live at the same time
+/// `#[state_lock(try_tear_up = path)]` tears up the state by a fallible `fn() -> io::Result<Self>`
+///
+/// the state is a `MemberOf` the family marker type of `#[family(..)]`, see `family!`
+#[proc_macro_derive(
+    State,
+    attributes(family, state_lock, transition_cost, compatible_with)
+)]
 pub fn derive_state(input: TokenStream) -> TokenStream {
     let mut ast = parse_macro_input!(input as syn::DeriveInput);
     // eprintln!("==========================================================");
@@ -18,9 +28,24 @@ pub fn derive_state(input: TokenStream) -> TokenStream {
     );
 
     // let state_lock_attr = get_attr("state_lock", ast.attrs);
-    let state_lock_path = match state_lock_path(&mut ast.attrs) {
+    let StateLockAttr {
+        state_lock_path,
+        try_tear_up,
+    } = match state_lock_attr(&mut ast.attrs) {
         Err(e) => return e.to_compile_error().into(),
-        Ok(p) => p,
+        Ok(attr) => attr,
+    };
+
+    let cost_attr = get_attr("transition_cost", ast.attrs.clone());
+    let transition_costs = match get_transition_costs_from_attr(cost_attr) {
+        Err(e) => return e.to_compile_error().into(),
+        Ok(c) => c,
+    };
+
+    let compatible_attr = get_attr("compatible_with", ast.attrs.clone());
+    let compatible_states = match get_compatible_states_from_attr(compatible_attr) {
+        Err(e) => return e.to_compile_error().into(),
+        Ok(c) => c,
     };
 
     let family_attr = get_attr("family", ast.attrs);
@@ -29,6 +54,77 @@ pub fn derive_state(input: TokenStream) -> TokenStream {
         Ok(f) => f,
     };
 
+    let transition_costs = transition_costs.iter().enumerate().map(|(i, (to, cost))| {
+        let static_ident = format_ident!("TRANSITION_COST_{}", i);
+        quote!(
+            #[#state_lock_path::linkme::distributed_slice(#state_lock_path::TRANSITION_COST_REGISTRATION)]
+            #[linkme(crate = #state_lock_path::linkme)]
+            static #static_ident: #state_lock_path::TransitionCostRegistration = #state_lock_path::TransitionCostRegistration {
+                state_family: #family,
+                from: stringify!(#struct_ident),
+                to: stringify!(#to),
+                cost: #cost,
+            };
+        )
+    });
+
+    let compatible_states = compatible_states.iter().enumerate().map(|(i, compatible)| {
+        let static_ident = format_ident!("COMPATIBLE_{}", i);
+        quote!(
+            #[#state_lock_path::linkme::distributed_slice(#state_lock_path::COMPATIBILITY_REGISTRATION)]
+            #[linkme(crate = #state_lock_path::linkme)]
+            static #static_ident: #state_lock_path::CompatibilityRegistration = #state_lock_path::CompatibilityRegistration {
+                state_family: #family,
+                state: stringify!(#struct_ident),
+                compatible: stringify!(#compatible),
+            };
+        )
+    });
+
+    let (try_tear_up_fn, registration) = match try_tear_up {
+        Some(try_tear_up) => (
+            quote!(
+                fn try_tear_up() -> std::io::Result<Self> {
+                    #try_tear_up()
+                }
+            ),
+            quote!(
+                impl super::#struct_ident {
+                    fn try_create_default() -> std::io::Result<Box<dyn #state_lock_path::State>> {
+                        Ok(Box::new(<Self as #state_lock_path::State>::try_tear_up()?))
+                    }
+                }
+
+                #[#state_lock_path::linkme::distributed_slice(#state_lock_path::FALLIBLE_STATE_REGISTRATION)]
+                #[linkme(crate = #state_lock_path::linkme)]
+                static STATE: #state_lock_path::FallibleStateRegistration =
+                    #state_lock_path::FallibleStateRegistration {
+                        state: stringify!(#struct_ident),
+                        state_family: #family,
+                        try_tear_up_fn: super::#struct_ident::try_create_default,
+                    };
+            ),
+        ),
+        None => (
+            quote!(),
+            quote!(
+                impl super::#struct_ident {
+                    fn create_default() -> Box<dyn #state_lock_path::State> {
+                        Box::new(<Self as #state_lock_path::State>::tear_up())
+                    }
+                }
+
+                #[#state_lock_path::linkme::distributed_slice(#state_lock_path::STATE_REGISTRATION)]
+                #[linkme(crate = #state_lock_path::linkme)]
+                static STATE: #state_lock_path::StateRegistration = #state_lock_path::StateRegistration {
+                    state: stringify!(#struct_ident),
+                    state_family: #family,
+                    tear_up_fn: super::#struct_ident::create_default,
+                };
+            ),
+        ),
+    };
+
     let out = quote!(
         pub use #impl_mod::*;
         #[allow(non_snake_case)]
@@ -48,20 +144,17 @@ pub fn derive_state(input: TokenStream) -> TokenStream {
                     use #state_lock_path::default::{HasDefault, NoDefaultImplement};
                     HasDefault::<Self>::tear_up()
                 }
+                #try_tear_up_fn
             }
-            impl super::#struct_ident {
-                fn create_default() -> Box<dyn #state_lock_path::State> {
-                    Box::new(Self::tear_up())
-                }
+            // the family marker type is named by the family name
+            impl #state_lock_path::MemberOf<#state_lock_path::FamilyMarker<{ #state_lock_path::family_id(#family) }>>
+                for super::#struct_ident
+            {
             }
+            #registration
 
-            #[#state_lock_path::linkme::distributed_slice(#state_lock_path::STATE_REGISTRATION)]
-            #[linkme(crate = #state_lock_path::linkme)]
-            static STATE: #state_lock_path::StateRegistration = #state_lock_path::StateRegistration {
-                state: stringify!(#struct_ident),
-                state_family: #family,
-                tear_up_fn: super::#struct_ident::create_default,
-            };
+            #(#transition_costs)*
+            #(#compatible_states)*
         }
     );
     // eprintln!("{}", out);
@@ -105,9 +198,43 @@ fn get_family_from_attr(attr: Option<syn::Attribute>) -> Result<proc_macro2::Tok
     }
 }
 
+// #[transition_cost(B = 1, C = 10)]
+fn get_transition_costs_from_attr(
+    attr: Option<syn::Attribute>,
+) -> Result<Vec<(syn::Ident, syn::Expr)>> {
+    let Some(attr) = attr else {
+        return Ok(Vec::new());
+    };
+
+    let args = attr.parse_args_with(Punctuated::<MetaNameValue, Token![,]>::parse_terminated)?;
+    args.into_iter()
+        .map(|arg| match arg.path.get_ident() {
+            Some(to) => Ok((to.clone(), arg.value)),
+            None => bail!(arg.path, "expected `transition_cost(State = cost)`"),
+        })
+        .collect()
+}
+
+// #[compatible_with(B, C)]
+fn get_compatible_states_from_attr(attr: Option<syn::Attribute>) -> Result<Vec<syn::Ident>> {
+    let Some(attr) = attr else {
+        return Ok(Vec::new());
+    };
+
+    let args = attr.parse_args_with(Punctuated::<syn::Ident, Token![,]>::parse_terminated)?;
+    Ok(args.into_iter().collect())
+}
+
 // #[state_lock(crate = path::to::state_lock)]
-fn state_lock_path(attrs: &mut Vec<Attribute>) -> Result<Path> {
+// #[state_lock(crate = path, try_tear_up = path)]
+struct StateLockAttr {
+    state_lock_path: Path,
+    try_tear_up: Option<Path>,
+}
+
+fn state_lock_attr(attrs: &mut Vec<Attribute>) -> Result<StateLockAttr> {
     let mut state_lock_path = None;
+    let mut try_tear_up = None;
     let mut errors: Option<Error> = None;
 
     attrs.retain(|attr| {
@@ -115,22 +242,39 @@ fn state_lock_path(attrs: &mut Vec<Attribute>) -> Result<Path> {
             return true;
         }
         let args = attr.parse_args_with(|input: ParseStream| {
-            input.parse::<Token![crate]>()?;
-            input.parse::<Token![=]>()?;
-            input.call(Path::parse_mod_style)
+            while !input.is_empty() {
+                if input.peek(Token![crate]) {
+                    input.parse::<Token![crate]>()?;
+                    input.parse::<Token![=]>()?;
+                    state_lock_path = Some(input.call(Path::parse_mod_style)?);
+                } else {
+                    let key = input.parse::<syn::Ident>()?;
+                    if key != "try_tear_up" {
+                        bail!(key, "expected `crate` or `try_tear_up`");
+                    }
+                    input.parse::<Token![=]>()?;
+                    try_tear_up = Some(input.parse::<Path>()?);
+                }
+                if !input.is_empty() {
+                    input.parse::<Token![,]>()?;
+                }
+            }
+            Ok(())
         });
-        match args {
-            Ok(path) => state_lock_path = Some(path),
-            Err(err) => match &mut errors {
+        if let Err(err) = args {
+            match &mut errors {
                 None => errors = Some(err),
                 Some(errors) => errors.combine(err),
-            },
+            }
         }
         false
     });
 
     match errors {
-        None => Ok(state_lock_path.unwrap_or_else(|| parse_quote!(::state_lock))),
+        None => Ok(StateLockAttr {
+            state_lock_path: state_lock_path.unwrap_or_else(|| parse_quote!(::state_lock)),
+            try_tear_up,
+        }),
         Some(errors) => Err(errors),
     }
 }
@@ -0,0 +1,93 @@
+use state_lock::{State, StateLock};
+
+use std::future::Future;
+use std::pin::pin;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::task::{Context, Poll, Wake, Waker};
+use std::thread::{self, Thread};
+use std::time::Duration;
+
+const STATE_FAMILY: &str = "StateAsync";
+
+static C_TEAR_UP: AtomicUsize = AtomicUsize::new(0);
+
+#[derive(State, Default)]
+#[family(STATE_FAMILY)]
+struct A;
+
+#[derive(State, Default)]
+#[family(STATE_FAMILY)]
+struct B;
+
+#[derive(State)]
+#[family(STATE_FAMILY)]
+struct C;
+
+impl Default for C {
+    fn default() -> Self {
+        C_TEAR_UP.fetch_add(1, Ordering::Relaxed);
+        C
+    }
+}
+
+// a minimal local executor
+struct ThreadWaker(Thread);
+
+impl Wake for ThreadWaker {
+    fn wake(self: Arc<Self>) {
+        self.0.unpark();
+    }
+}
+
+fn thread_waker() -> Waker {
+    Arc::new(ThreadWaker(thread::current())).into()
+}
+
+fn block_on<F: Future>(fut: F) -> F::Output {
+    let waker = thread_waker();
+    let mut cx = Context::from_waker(&waker);
+    let mut fut = pin!(fut);
+    loop {
+        match fut.as_mut().poll(&mut cx) {
+            Poll::Ready(output) => return output,
+            Poll::Pending => thread::park(),
+        }
+    }
+}
+
+#[test]
+fn lock_async() {
+    let state_lock = Arc::new(StateLock::new(STATE_FAMILY));
+    let a = block_on(state_lock.lock_async::<A>()).unwrap();
+    assert_eq!(a.name(), "A");
+
+    let state_lock_1 = state_lock.clone();
+    let waiter = thread::spawn(move || {
+        let b = block_on(state_lock_1.lock_async::<B>()).unwrap();
+        b.name()
+    });
+
+    thread::sleep(Duration::from_millis(100));
+    assert_eq!(state_lock.current_state().unwrap().name(), "A");
+    drop(a);
+    assert_eq!(waiter.join().unwrap(), "B");
+}
+
+#[test]
+fn drop_pending_future() {
+    let state_lock = StateLock::new(STATE_FAMILY);
+    let a = state_lock.lock::<A>().unwrap();
+
+    {
+        let waker = thread_waker();
+        let mut cx = Context::from_waker(&waker);
+        let mut fut = pin!(state_lock.lock_by_state_name_async("C"));
+        assert!(fut.as_mut().poll(&mut cx).is_pending());
+    }
+
+    // the dropped future is removed from the waiter queue
+    drop(a);
+    assert!(state_lock.current_state().is_none());
+    assert_eq!(C_TEAR_UP.load(Ordering::Relaxed), 0);
+}
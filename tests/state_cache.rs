@@ -0,0 +1,190 @@
+use state_lock::{CacheCapacity, State, StateLock};
+
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Duration;
+
+const LRU_FAMILY: &str = "StateCacheLru";
+const WEIGHTED_FAMILY: &str = "StateCacheWeighted";
+
+static A_TEAR_UP: AtomicUsize = AtomicUsize::new(0);
+static B_DROPPED: AtomicUsize = AtomicUsize::new(0);
+
+#[derive(State)]
+#[family(LRU_FAMILY)]
+struct A;
+
+impl Default for A {
+    fn default() -> Self {
+        A_TEAR_UP.fetch_add(1, Ordering::Relaxed);
+        A
+    }
+}
+
+#[derive(State, Default)]
+#[family(LRU_FAMILY)]
+struct B;
+
+impl Drop for B {
+    fn drop(&mut self) {
+        B_DROPPED.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+#[derive(State, Default)]
+#[family(LRU_FAMILY)]
+struct C;
+
+#[test]
+fn lru_cache() {
+    let state_lock = StateLock::new(LRU_FAMILY).with_state_cache(CacheCapacity::Lru(2));
+
+    drop(state_lock.lock::<A>().unwrap());
+    drop(state_lock.lock::<B>().unwrap());
+    assert_eq!(state_lock.cached_states(), ["A", "B"]);
+
+    // A is reused instead of tear up again
+    drop(state_lock.lock::<A>().unwrap());
+    assert_eq!(A_TEAR_UP.load(Ordering::Relaxed), 1);
+    assert_eq!(state_lock.cached_states(), ["B", "A"]);
+
+    // B is the least recently used one, evicted for C
+    let c = state_lock.lock::<C>().unwrap();
+    assert_eq!(B_DROPPED.load(Ordering::Relaxed), 1);
+    assert_eq!(state_lock.cached_states(), ["A"]);
+    drop(c);
+
+    state_lock.clear_cache();
+    assert!(state_lock.cached_states().is_empty());
+}
+
+#[derive(State, Default)]
+#[family(WEIGHTED_FAMILY)]
+struct X;
+
+struct Y;
+
+impl Y {
+    fn make() -> Box<dyn State> {
+        Box::new(Y::tear_up())
+    }
+}
+
+impl State for Y {
+    fn state_name() -> &'static str {
+        stringify!(Y)
+    }
+    fn name(&self) -> &'static str {
+        Self::state_name()
+    }
+    fn family(&self) -> &'static str {
+        WEIGHTED_FAMILY
+    }
+    fn tear_up() -> Self {
+        Y
+    }
+    fn size_hint(&self) -> usize {
+        2
+    }
+}
+
+#[state_lock::linkme::distributed_slice(state_lock::STATE_REGISTRATION)]
+#[linkme(crate = state_lock::linkme)]
+static STATE_Y: state_lock::StateRegistration = state_lock::StateRegistration {
+    state_family: WEIGHTED_FAMILY,
+    state: stringify!(Y),
+    tear_up_fn: Y::make,
+};
+
+#[derive(State, Default)]
+#[family(WEIGHTED_FAMILY)]
+struct Z;
+
+#[test]
+fn weighted_cache() {
+    let state_lock = StateLock::new(WEIGHTED_FAMILY).with_state_cache(CacheCapacity::Weighted(3));
+
+    drop(state_lock.lock_by_state_name("X").unwrap());
+    drop(state_lock.lock_by_state_name("Y").unwrap());
+    assert_eq!(state_lock.cached_states(), ["X", "Y"]);
+
+    // X and Y already take all the weight
+    drop(state_lock.lock_by_state_name("Z").unwrap());
+    assert_eq!(state_lock.cached_states(), ["Y", "Z"]);
+
+    drop(state_lock.lock_by_state_name("X").unwrap());
+    assert_eq!(state_lock.cached_states(), ["Z", "X"]);
+
+    // Y is known to be heavy, Z is evicted to make room for it
+    let y = state_lock.lock_by_state_name("Y").unwrap();
+    assert_eq!(state_lock.cached_states(), ["X"]);
+    drop(y);
+    assert_eq!(state_lock.cached_states(), ["X", "Y"]);
+}
+
+const IDLE_FAMILY: &str = "StateCacheIdle";
+
+static I_DROPPED: AtomicUsize = AtomicUsize::new(0);
+
+#[derive(State, Default)]
+#[family(IDLE_FAMILY)]
+struct I;
+
+impl Drop for I {
+    fn drop(&mut self) {
+        I_DROPPED.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+#[test]
+fn idle_timeout() {
+    let state_lock = StateLock::new(IDLE_FAMILY).with_idle_timeout(Duration::from_millis(100));
+
+    drop(state_lock.lock::<I>().unwrap());
+    assert_eq!(state_lock.cached_states(), ["I"]);
+    assert_eq!(I_DROPPED.load(Ordering::Relaxed), 0);
+
+    // the idle state is tear down in the background
+    std::thread::sleep(Duration::from_millis(300));
+    assert!(state_lock.cached_states().is_empty());
+    assert_eq!(I_DROPPED.load(Ordering::Relaxed), 1);
+
+    // a live state is never expired
+    let i = state_lock.lock::<I>().unwrap();
+    std::thread::sleep(Duration::from_millis(300));
+    assert_eq!(I_DROPPED.load(Ordering::Relaxed), 1);
+    drop(i);
+    assert_eq!(state_lock.cached_states(), ["I"]);
+}
+
+const EVICT_FAMILY: &str = "StateCacheEvict";
+
+#[derive(State, Default)]
+#[family(EVICT_FAMILY)]
+struct E;
+
+#[derive(State, Default)]
+#[family(EVICT_FAMILY)]
+struct G;
+
+#[test]
+fn evict_idle() {
+    let state_lock = StateLock::new(EVICT_FAMILY).with_state_cache(CacheCapacity::Lru(2));
+    drop(state_lock.lock::<E>().unwrap());
+    drop(state_lock.lock::<G>().unwrap());
+    assert_eq!(state_lock.cached_states(), ["E", "G"]);
+
+    // no idle timeout, all the cached states are idle
+    state_lock.evict_idle();
+    assert!(state_lock.cached_states().is_empty());
+
+    // the states are not idle long enough
+    let state_lock = state_lock.with_idle_timeout(Duration::from_secs(10));
+    drop(state_lock.lock::<E>().unwrap());
+    drop(state_lock.lock::<G>().unwrap());
+    state_lock.evict_idle();
+    assert_eq!(state_lock.cached_states(), ["E", "G"]);
+
+    // the idle states are still cleared
+    state_lock.clear_cache();
+    assert!(state_lock.cached_states().is_empty());
+}
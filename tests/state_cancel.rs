@@ -0,0 +1,50 @@
+use may::go;
+use state_lock::{State, StateLock};
+
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+const STATE_FAMILY: &str = "StateCancel";
+
+static B_TEAR_UP: AtomicUsize = AtomicUsize::new(0);
+
+#[derive(State, Default)]
+#[family(STATE_FAMILY)]
+struct A;
+
+#[derive(State)]
+#[family(STATE_FAMILY)]
+struct B;
+
+impl Default for B {
+    fn default() -> Self {
+        B_TEAR_UP.fetch_add(1, Ordering::Relaxed);
+        B
+    }
+}
+
+#[derive(State, Default)]
+#[family(STATE_FAMILY)]
+struct C;
+
+#[test]
+fn cancel_waiter() {
+    let state_lock = Arc::new(StateLock::new(STATE_FAMILY));
+    let a = state_lock.lock::<A>().unwrap();
+
+    let state_lock_1 = state_lock.clone();
+    let cancelled = go!(move || drop(state_lock_1.lock::<B>().unwrap()));
+    let state_lock_1 = state_lock.clone();
+    let waiter = go!(move || state_lock_1.lock::<C>().unwrap().name());
+    std::thread::sleep(Duration::from_millis(50));
+
+    unsafe { cancelled.coroutine().cancel() };
+    assert!(cancelled.join().is_err());
+
+    // the emptied group is never activated
+    drop(a);
+    assert_eq!(waiter.join().unwrap(), "C");
+    assert_eq!(B_TEAR_UP.load(Ordering::Relaxed), 0);
+    assert_eq!(state_lock.stats().get("B").map(|b| b.acquisitions), None);
+}
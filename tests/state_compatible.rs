@@ -0,0 +1,97 @@
+use may::go;
+use state_lock::{State, StateEvent, StateLock};
+
+use std::io;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+const STATE_FAMILY: &str = "StateCompatible";
+
+// read only state, could be live with any serve state
+#[derive(State, Default)]
+#[family(STATE_FAMILY)]
+#[compatible_with(ServeA, ServeB)]
+struct Inspect;
+
+#[derive(State, Default)]
+#[family(STATE_FAMILY)]
+struct ServeA;
+
+#[derive(State, Default)]
+#[family(STATE_FAMILY)]
+struct ServeB;
+
+fn current_states(state_lock: &StateLock) -> Vec<&'static str> {
+    let states = state_lock.current_states();
+    states.iter().map(|s| s.name()).collect()
+}
+
+#[test]
+fn compatible_states() {
+    let state_lock = Arc::new(StateLock::new(STATE_FAMILY));
+
+    // Inspect is granted immediately while ServeA is active
+    let serve_a = state_lock.lock::<ServeA>().unwrap();
+    let inspect = state_lock.try_lock::<Inspect>().unwrap();
+    assert_eq!(current_states(&state_lock), ["ServeA", "Inspect"]);
+
+    // ServeB has to wait for ServeA
+    let err = state_lock.try_lock::<ServeB>().unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::WouldBlock);
+
+    let locked = Arc::new(AtomicBool::new(false));
+    let state_lock_1 = state_lock.clone();
+    let locked_1 = locked.clone();
+    let waiter = go!(move || {
+        let serve_b = state_lock_1.lock::<ServeB>().unwrap();
+        locked_1.store(true, Ordering::Relaxed);
+        drop(serve_b);
+    });
+    std::thread::sleep(Duration::from_millis(100));
+    assert!(!locked.load(Ordering::Relaxed));
+
+    // ServeB is activated while Inspect is still active
+    drop(serve_a);
+    waiter.join().unwrap();
+    assert!(locked.load(Ordering::Relaxed));
+    assert_eq!(current_states(&state_lock), ["Inspect"]);
+
+    drop(inspect);
+    assert!(state_lock.current_state().is_none());
+}
+
+#[test]
+fn fair_group_waits_behind_blocked_group() {
+    let state_lock = Arc::new(StateLock::new(STATE_FAMILY).with_fairness(true));
+    let events = state_lock.subscribe();
+    let serve_a = state_lock.lock::<ServeA>().unwrap();
+    let inspect = state_lock.lock::<Inspect>().unwrap();
+
+    // ServeB waits for ServeA
+    let state_lock_1 = state_lock.clone();
+    let serve_b_waiter = go!(move || state_lock_1.lock::<ServeB>().map(|s| s.name()));
+    std::thread::sleep(Duration::from_millis(100));
+
+    // in fair mode, the new caller for ServeA queues behind ServeB
+    let state_lock_2 = state_lock.clone();
+    let serve_a_waiter = go!(move || state_lock_2.lock::<ServeA>().map(|s| s.name()));
+    std::thread::sleep(Duration::from_millis(100));
+    assert!(!serve_a_waiter.is_done());
+
+    // releasing Inspect wakes up the groups, the ServeA group still waits behind ServeB
+    drop(inspect);
+    std::thread::sleep(Duration::from_millis(100));
+    assert!(!serve_a_waiter.is_done());
+    assert!(!serve_b_waiter.is_done());
+
+    drop(serve_a);
+    assert_eq!(serve_b_waiter.join().unwrap().unwrap(), "ServeB");
+    assert_eq!(serve_a_waiter.join().unwrap().unwrap(), "ServeA");
+    let activated = events.try_iter().filter_map(|e| match e {
+        StateEvent::Activated { state } => Some(state),
+        _ => None,
+    });
+    let activated = activated.collect::<Vec<_>>();
+    assert_eq!(activated, ["ServeA", "Inspect", "ServeB", "ServeA"]);
+}
@@ -0,0 +1,104 @@
+use may::go;
+use state_lock::{LowestTransitionCost, State, StateLock};
+
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+const STATE_FAMILY: &str = "StateCost";
+
+#[derive(State, Default)]
+#[family(STATE_FAMILY)]
+#[transition_cost(B = 1, C = 10, D = 100)]
+struct A;
+
+#[derive(State, Default)]
+#[family(STATE_FAMILY)]
+#[transition_cost(C = 1, D = 50)]
+struct B;
+
+#[derive(State, Default)]
+#[family(STATE_FAMILY)]
+struct C;
+
+#[derive(State, Default)]
+#[family(STATE_FAMILY)]
+struct D;
+
+// lock the `first` state, then queue the waiters in order, return the activated order
+fn activated_order(
+    state_lock: StateLock,
+    first: &str,
+    names: &[&'static str],
+) -> Vec<&'static str> {
+    let state_lock = Arc::new(state_lock);
+    let a = state_lock.lock_by_state_name(first).unwrap();
+    let order = Arc::new(Mutex::new(Vec::new()));
+
+    let mut waiters = Vec::new();
+    for &name in names {
+        let state_lock = state_lock.clone();
+        let order = order.clone();
+        waiters.push(go!(move || {
+            let state = state_lock.lock_by_state_name(name).unwrap();
+            order.lock().unwrap().push(state.name());
+        }));
+        std::thread::sleep(Duration::from_millis(50));
+    }
+
+    drop(a);
+    for waiter in waiters {
+        waiter.join().unwrap();
+    }
+    let order = order.lock().unwrap();
+    order.clone()
+}
+
+#[test]
+fn lowest_transition_cost() {
+    let policy = LowestTransitionCost::new(Duration::from_secs(10));
+    let state_lock = StateLock::new(STATE_FAMILY).with_scheduling_policy(policy);
+    // A -> B -> C -> D instead of the arrival order
+    let order = activated_order(state_lock, "A", &["D", "C", "B"]);
+    assert_eq!(order, ["B", "C", "D"]);
+}
+
+#[test]
+fn lowest_transition_cost_bounded_wait() {
+    let policy = LowestTransitionCost::new(Duration::from_millis(50));
+    let state_lock = StateLock::new(STATE_FAMILY).with_scheduling_policy(policy);
+    // D waits too long, it goes first though it's the most expensive one
+    let order = activated_order(state_lock, "A", &["D", "C", "B"]);
+    assert_eq!(order, ["D", "C", "B"]);
+}
+
+const TOTAL_FAMILY: &str = "StateTotalCost";
+
+#[derive(State, Default)]
+#[family(TOTAL_FAMILY)]
+#[transition_cost(Q = 1, R = 2, S = 50)]
+struct P;
+
+#[derive(State, Default)]
+#[family(TOTAL_FAMILY)]
+#[transition_cost(R = 100, S = 100)]
+struct Q;
+
+#[derive(State, Default)]
+#[family(TOTAL_FAMILY)]
+#[transition_cost(S = 1)]
+struct R;
+
+#[derive(State, Default)]
+#[family(TOTAL_FAMILY)]
+#[transition_cost(Q = 1)]
+struct S;
+
+#[test]
+fn lowest_total_transition_cost() {
+    let policy = LowestTransitionCost::new(Duration::from_secs(10));
+    let state_lock = StateLock::new(TOTAL_FAMILY).with_scheduling_policy(policy);
+    // P -> Q is the cheapest next transition, but P -> R -> S -> Q costs 4 in total
+    // while P -> Q -> R -> S costs 102
+    let order = activated_order(state_lock, "P", &["Q", "R", "S"]);
+    assert_eq!(order, ["R", "S", "Q"]);
+}
@@ -0,0 +1,116 @@
+use may::go;
+use state_lock::{State, StateLock};
+
+use std::io;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+const STATE_FAMILY: &str = "StateDeadlock";
+
+#[derive(State, Default)]
+#[family(STATE_FAMILY)]
+struct A;
+
+#[derive(State, Default)]
+#[family(STATE_FAMILY)]
+struct B;
+
+#[derive(State, Default)]
+#[family(STATE_FAMILY)]
+#[compatible_with(A, B)]
+struct C;
+
+#[test]
+fn lock_other_state_while_holding() {
+    let state_lock = Arc::new(StateLock::new(STATE_FAMILY).with_deadlock_detection(true));
+
+    let a = state_lock.lock::<A>().unwrap();
+    let err = state_lock.lock::<B>().unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::Deadlock);
+
+    // other coroutines just wait for the state
+    let done = Arc::new(AtomicBool::new(false));
+    let state_lock_1 = state_lock.clone();
+    let done_1 = done.clone();
+    let waiter = go!(move || {
+        let b = state_lock_1.lock::<B>().unwrap();
+        assert_eq!(b.name(), "B");
+        done_1.store(true, Ordering::Relaxed);
+    });
+    std::thread::sleep(Duration::from_millis(50));
+    assert!(!done.load(Ordering::Relaxed));
+
+    drop(a);
+    waiter.join().unwrap();
+}
+
+#[test]
+fn reentrant_lock_in_fair_mode() {
+    let state_lock = StateLock::new(STATE_FAMILY)
+        .with_fairness(true)
+        .with_deadlock_detection(true);
+    let state_lock = Arc::new(state_lock);
+
+    let a = state_lock.lock::<A>().unwrap();
+    let state_lock_1 = state_lock.clone();
+    let waiter = go!(move || {
+        let b = state_lock_1.lock::<B>().unwrap();
+        assert_eq!(b.name(), "B");
+    });
+    std::thread::sleep(Duration::from_millis(50));
+
+    // new A lockers wait behind B, but the holder of A still gets it
+    let a_1 = state_lock.lock::<A>().unwrap();
+    assert_eq!(a_1.name(), "A");
+    drop(a);
+    drop(a_1);
+    waiter.join().unwrap();
+}
+
+#[test]
+fn hold_compatible_state() {
+    let state_lock = StateLock::new(STATE_FAMILY).with_deadlock_detection(true);
+
+    let b = state_lock.lock::<B>().unwrap();
+    let c = state_lock.lock::<C>().unwrap();
+    std::thread::scope(|s| {
+        // C never blocks A, so the holder of C just waits for B
+        let a = s.spawn(|| {
+            let c = state_lock.lock::<C>().unwrap();
+            let a = state_lock.lock::<A>().unwrap();
+            (c.name(), a.name())
+        });
+        std::thread::sleep(Duration::from_millis(50));
+        assert!(!a.is_finished());
+        drop(b);
+        assert_eq!(a.join().unwrap(), ("C", "A"));
+    });
+    drop(c);
+}
+
+#[test]
+fn detection_is_off_by_default() {
+    let state_lock = StateLock::new(STATE_FAMILY);
+
+    let a = state_lock.lock::<A>().unwrap();
+    let err = state_lock.lock_timeout::<B>(Duration::from_millis(50));
+    assert_eq!(err.unwrap_err().kind(), io::ErrorKind::TimedOut);
+    drop(a);
+}
+
+#[test]
+fn moved_guard() {
+    let state_lock = StateLock::new(STATE_FAMILY);
+
+    let a = state_lock.lock::<A>().unwrap();
+    std::thread::scope(|s| {
+        // the guard is moved away without being used, waiting for B is not a deadlock
+        s.spawn(move || {
+            std::thread::sleep(Duration::from_millis(50));
+            drop(a);
+        });
+        let b = state_lock.lock::<B>().unwrap();
+        assert_eq!(b.name(), "B");
+    });
+}
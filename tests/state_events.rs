@@ -0,0 +1,43 @@
+use state_lock::{State, StateEvent, StateLock};
+
+const STATE_FAMILY: &str = "StateEvents";
+
+#[derive(State, Default)]
+#[family(STATE_FAMILY)]
+struct A;
+
+#[derive(State, Default)]
+#[family(STATE_FAMILY)]
+struct B;
+
+#[test]
+fn state_events() {
+    let state_lock = StateLock::new(STATE_FAMILY);
+    let events = state_lock.subscribe();
+
+    let a = state_lock.lock::<A>().unwrap();
+    drop(a);
+    // A is reused from cache
+    drop(state_lock.lock::<A>().unwrap());
+    // A is tear down to make room for B
+    drop(state_lock.lock::<B>().unwrap());
+    drop(state_lock);
+
+    let events = events.iter().collect::<Vec<_>>();
+    assert_eq!(
+        events,
+        [
+            StateEvent::TearUpStarted { state: "A" },
+            StateEvent::Activated { state: "A" },
+            StateEvent::Released { state: "A" },
+            StateEvent::Activated { state: "A" },
+            StateEvent::Released { state: "A" },
+            StateEvent::TearDownFinished { state: "A" },
+            StateEvent::TearUpStarted { state: "B" },
+            StateEvent::Activated { state: "B" },
+            StateEvent::Released { state: "B" },
+            // the cached state is tear down when the state lock is dropped
+            StateEvent::TearDownFinished { state: "B" },
+        ]
+    );
+}
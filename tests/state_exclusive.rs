@@ -0,0 +1,71 @@
+use may::go;
+use state_lock::{State, StateLock};
+
+use std::io;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+const STATE_FAMILY: &str = "StateExclusive";
+
+static COUNTER_TEAR_UP: AtomicUsize = AtomicUsize::new(0);
+
+#[derive(State)]
+#[family(STATE_FAMILY)]
+struct Counter {
+    value: AtomicUsize,
+    config: usize,
+}
+
+impl Default for Counter {
+    fn default() -> Self {
+        COUNTER_TEAR_UP.fetch_add(1, Ordering::Relaxed);
+        Counter {
+            value: AtomicUsize::new(0),
+            config: 0,
+        }
+    }
+}
+
+#[test]
+fn exclusive_state() {
+    let state_lock = Arc::new(StateLock::new(STATE_FAMILY));
+
+    let mut counter = state_lock.lock_exclusive::<Counter>().unwrap();
+    counter.config = 1;
+    // no one could join the state held exclusively
+    let err = state_lock.try_lock::<Counter>().unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::WouldBlock);
+    assert!(state_lock.current_state().is_none());
+
+    let state_lock_1 = state_lock.clone();
+    let waiter = go!(move || {
+        let counter = state_lock_1.lock::<Counter>().unwrap();
+        assert_eq!(counter.config, 1);
+        counter.value.fetch_add(1, Ordering::Relaxed);
+        // hold the state until the exclusive request is queued
+        std::thread::sleep(Duration::from_millis(200));
+    });
+    std::thread::sleep(Duration::from_millis(100));
+
+    // the waiter joins the state after downgrade
+    let counter = counter.downgrade();
+    std::thread::sleep(Duration::from_millis(50));
+    assert_eq!(counter.value.load(Ordering::Relaxed), 1);
+
+    // other guards exist, could not upgrade
+    let counter = counter.try_upgrade().unwrap_err();
+    drop(counter);
+
+    // granted after the waiter releases the state, the state is reused
+    let mut counter = state_lock.lock_exclusive::<Counter>().unwrap();
+    waiter.join().unwrap();
+    counter.config = 2;
+    assert_eq!(COUNTER_TEAR_UP.load(Ordering::Relaxed), 1);
+
+    // upgrade and downgrade on the same state without transition
+    let counter = counter.downgrade();
+    let counter = counter.try_upgrade().unwrap();
+    assert_eq!(counter.config, 2);
+    assert_eq!(COUNTER_TEAR_UP.load(Ordering::Relaxed), 1);
+}
@@ -0,0 +1,73 @@
+use may::go;
+use state_lock::{State, StateLock};
+
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+const STATE_FAMILY: &str = "StateFair";
+
+#[derive(State, Default)]
+#[family(STATE_FAMILY)]
+struct A;
+
+#[derive(State, Default)]
+#[family(STATE_FAMILY)]
+struct B;
+
+#[test]
+fn fair_bounded_waiting() {
+    let state_lock = Arc::new(StateLock::new(STATE_FAMILY).with_fairness(true));
+    let stop = Arc::new(AtomicBool::new(false));
+
+    // a steady stream of overlapped A lockers, A would never be released in unfair mode
+    let mut workers = Vec::new();
+    for _ in 0..3 {
+        let state_lock = state_lock.clone();
+        let stop = stop.clone();
+        workers.push(go!(move || {
+            let mut count = 0;
+            while !stop.load(Ordering::Relaxed) {
+                let a = state_lock.lock::<A>().unwrap();
+                std::thread::sleep(Duration::from_millis(10));
+                drop(a);
+                count += 1;
+            }
+            count
+        }));
+        std::thread::sleep(Duration::from_millis(3));
+    }
+    std::thread::sleep(Duration::from_millis(50));
+
+    let start = Instant::now();
+    let b = state_lock
+        .lock_timeout::<B>(Duration::from_secs(1))
+        .unwrap();
+    assert!(start.elapsed() < Duration::from_millis(500));
+    assert_eq!(state_lock.current_state().unwrap().name(), "B");
+    drop(b);
+
+    // A lockers keep going after B is released
+    std::thread::sleep(Duration::from_millis(50));
+    stop.store(true, Ordering::Relaxed);
+    for worker in workers {
+        assert!(worker.join().unwrap() > 1);
+    }
+}
+
+#[test]
+fn fair_try_lock() {
+    let state_lock = Arc::new(StateLock::new(STATE_FAMILY).with_fairness(true));
+    let a = state_lock.lock::<A>().unwrap();
+
+    let state_lock_1 = state_lock.clone();
+    let b_waiter = go!(move || state_lock_1.lock::<B>().map(|b| b.name()));
+    std::thread::sleep(Duration::from_millis(50));
+
+    // B is waiting, new A callers could not join
+    assert!(state_lock.try_lock::<A>().is_err());
+    assert!(state_lock.lock_if_current("A").is_err());
+
+    drop(a);
+    assert_eq!(b_waiter.join().unwrap().unwrap(), "B");
+}
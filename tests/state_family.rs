@@ -0,0 +1,97 @@
+use may::go;
+use state_lock::{family, Family, MemberOf, State, StateGuard, StateLock, StateLockSet};
+
+use std::sync::Arc;
+
+const STATE_FAMILY: &str = "StateFamily";
+const OTHER_FAMILY: &str = "StateOtherFamily";
+
+type Fam = family!(STATE_FAMILY);
+type Other = family!(OTHER_FAMILY);
+
+#[derive(State, Default)]
+#[family(STATE_FAMILY)]
+struct A;
+
+#[derive(State, Default)]
+#[family(STATE_FAMILY)]
+struct B;
+
+#[derive(State, Default)]
+#[family(OTHER_FAMILY)]
+struct X;
+
+fn is_member<T: MemberOf<F>, F: Family>() {}
+
+#[test]
+fn typed_family() {
+    assert_eq!(Fam::family_name(), STATE_FAMILY);
+    assert_eq!(Other::family_name(), OTHER_FAMILY);
+    is_member::<A, Fam>();
+    is_member::<X, Other>();
+
+    let state_lock = Arc::new(StateLock::<Fam>::typed());
+    // `state_lock.lock::<X>()` would not compile, X is not a member of the family
+    let a = state_lock.lock::<A>().unwrap();
+    assert_eq!(a.name(), "A");
+    drop(a);
+
+    let state_lock_1 = state_lock.clone();
+    let b = go!(move || state_lock_1.lock::<B>().unwrap().name())
+        .join()
+        .unwrap();
+    assert_eq!(b, "B");
+
+    // the name based API is still available
+    let b = state_lock.lock_by_state_name("B").unwrap();
+    assert_eq!(b.name(), "B");
+    assert_eq!(state_lock.state_family(), STATE_FAMILY);
+}
+
+#[test]
+fn typed_builder() {
+    let state_lock = StateLock::<Other>::typed().with_fairness(true);
+    let x = state_lock.try_lock::<X>().unwrap();
+    assert_eq!(x.family(), OTHER_FAMILY);
+}
+
+#[test]
+fn typed_lock_owned() {
+    let state_lock = Arc::new(StateLock::<Fam>::typed());
+    let a = state_lock.lock_owned::<A>().unwrap();
+    let a = go!(move || a.name()).join().unwrap();
+    assert_eq!(a, "A");
+}
+
+#[test]
+fn typed_custom_tear_up() {
+    let state_lock = StateLock::<Fam>::typed_with_custom_tear_up(|name| match name {
+        "A" => Box::new(A),
+        _ => Box::new(B),
+    });
+    assert_eq!(state_lock.lock::<A>().unwrap().name(), "A");
+
+    let state_lock = StateLock::<Other>::typed_with_try_custom_tear_up(|name| {
+        Err(std::io::Error::other(format!("no {name}")))
+    });
+    let err = state_lock.lock::<X>().unwrap_err();
+    assert_eq!(err.to_string(), "no X");
+}
+
+#[test]
+fn typed_switch_to() {
+    let state_lock = StateLock::<Fam>::typed();
+    let a = state_lock.lock::<A>().unwrap();
+    // the guard keeps the family, `a.switch_to::<X>()` would not compile
+    let b: StateGuard<B, Fam> = a.switch_to::<B>().unwrap();
+    assert_eq!(b.name(), "B");
+}
+
+#[test]
+fn typed_lock_all() {
+    let state_lock_1 = StateLock::<Fam>::typed();
+    let state_lock_2 = StateLock::<Fam>::typed();
+    let states = StateLockSet::lock_all(&[(&state_lock_1, "A"), (&state_lock_2, "B")]).unwrap();
+    let names = states.iter().map(|s| s.name()).collect::<Vec<_>>();
+    assert_eq!(names, ["A", "B"]);
+}
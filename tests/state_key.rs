@@ -0,0 +1,68 @@
+use may::go;
+use state_lock::{KeyedState, State, StateLock};
+
+use std::io;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+const STATE_FAMILY: &str = "StateKey";
+
+static MODEL_TEAR_UP: AtomicUsize = AtomicUsize::new(0);
+
+#[derive(State, Default)]
+#[family(STATE_FAMILY)]
+struct Model {
+    config: String,
+}
+
+impl KeyedState for Model {
+    type Key = String;
+
+    fn tear_up_with_key(key: &String) -> io::Result<Self> {
+        MODEL_TEAR_UP.fetch_add(1, Ordering::Relaxed);
+        if key.is_empty() {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "empty config"));
+        }
+        let config = key.clone();
+        Ok(Model { config })
+    }
+}
+
+#[test]
+fn keyed_state() {
+    let state_lock = Arc::new(StateLock::new(STATE_FAMILY));
+
+    let x = state_lock.lock_with_key::<Model>("x".into()).unwrap();
+    assert_eq!(x.config, "x");
+    // the same key shares the live state
+    let x1 = state_lock.lock_with_key::<Model>("x".into()).unwrap();
+    assert_eq!(MODEL_TEAR_UP.load(Ordering::Relaxed), 1);
+
+    // a different key is a different state
+    let locked = Arc::new(AtomicBool::new(false));
+    let state_lock_1 = state_lock.clone();
+    let locked_1 = locked.clone();
+    let waiter = go!(move || {
+        let y = state_lock_1.lock_with_key::<Model>("y".into()).unwrap();
+        assert_eq!(y.config, "y");
+        locked_1.store(true, Ordering::Relaxed);
+    });
+    std::thread::sleep(Duration::from_millis(100));
+    assert!(!locked.load(Ordering::Relaxed));
+
+    drop(x);
+    drop(x1);
+    waiter.join().unwrap();
+    assert!(locked.load(Ordering::Relaxed));
+    assert_eq!(MODEL_TEAR_UP.load(Ordering::Relaxed), 2);
+
+    // the tear up error is returned
+    let err = state_lock.lock_with_key::<Model>("".into()).unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+
+    // the keyed state is different from the one locked without key
+    let model = state_lock.lock::<Model>().unwrap();
+    assert_eq!(model.config, "");
+    assert_eq!(MODEL_TEAR_UP.load(Ordering::Relaxed), 3);
+}
@@ -0,0 +1,68 @@
+use may::go;
+use state_lock::{MappedStateGuard, RawState, State, StateGuard, StateLock};
+
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+const STATE_FAMILY: &str = "StateMap";
+
+#[derive(Default)]
+struct Pool {
+    connections: Vec<usize>,
+}
+
+#[derive(State)]
+#[family(STATE_FAMILY)]
+struct Model {
+    pool: Pool,
+}
+
+impl Default for Model {
+    fn default() -> Self {
+        Model {
+            pool: Pool {
+                connections: vec![1, 2, 3],
+            },
+        }
+    }
+}
+
+#[derive(State, Default)]
+#[family(STATE_FAMILY)]
+struct B;
+
+fn pool_size(pool: &Pool) -> usize {
+    pool.connections.len()
+}
+
+#[test]
+fn mapped_guard() {
+    let state_lock = Arc::new(StateLock::new(STATE_FAMILY));
+
+    let model = state_lock.lock::<Model>().unwrap();
+    let pool = StateGuard::map(model, |m| &m.pool);
+    assert_eq!(pool_size(&pool), 3);
+    let first = MappedStateGuard::map(pool, |p| &p.connections[0]);
+    assert_eq!(*first, 1);
+    assert_eq!(first.name(), "Model");
+
+    // the state stays active with the mapped guard
+    let done = Arc::new(AtomicBool::new(false));
+    let state_lock_1 = state_lock.clone();
+    let done_1 = done.clone();
+    let waiter = go!(move || {
+        drop(state_lock_1.lock::<B>().unwrap());
+        done_1.store(true, Ordering::Relaxed);
+    });
+    std::thread::sleep(Duration::from_millis(50));
+    assert!(!done.load(Ordering::Relaxed));
+
+    drop(first);
+    waiter.join().unwrap();
+    assert!(done.load(Ordering::Relaxed));
+
+    let raw = state_lock.lock_by_state_name("B").unwrap();
+    let name = RawState::map(raw, |s| s.name());
+    assert_eq!(&*name, "B");
+}
@@ -0,0 +1,56 @@
+use may::go;
+use state_lock::{State, StateEvent, StateLock};
+
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+const STATE_FAMILY: &str = "StateOwned";
+
+#[derive(State, Default)]
+#[family(STATE_FAMILY)]
+struct A {
+    value: AtomicUsize,
+}
+
+#[derive(State, Default)]
+#[family(STATE_FAMILY)]
+struct B;
+
+#[test]
+fn move_owned_guard() {
+    let state_lock = Arc::new(StateLock::new(STATE_FAMILY));
+
+    let a = state_lock.lock_owned::<A>().unwrap();
+    let raw = state_lock.lock_by_state_name_owned("A").unwrap();
+    let holder = go!(move || {
+        a.value.fetch_add(1, Ordering::Relaxed);
+        std::thread::sleep(Duration::from_millis(100));
+        let a = raw.into_guard::<A>();
+        a.value.load(Ordering::Relaxed)
+    });
+
+    // the owned guards are moved away, waiting for B is not a deadlock
+    let b = state_lock.lock::<B>().unwrap();
+    assert_eq!(b.name(), "B");
+    assert_eq!(holder.join().unwrap(), 1);
+}
+
+#[test]
+fn owned_guard_keeps_lock() {
+    let state_lock = Arc::new(StateLock::new(STATE_FAMILY));
+    let events = state_lock.subscribe();
+
+    let a = state_lock.lock_owned::<A>().unwrap();
+    drop(state_lock);
+    assert_eq!(a.name(), "A");
+    assert_eq!(a.state_lock().cached_states().len(), 0);
+
+    // the state lock is dropped with the last guard
+    drop(a);
+    let events = events.iter().collect::<Vec<_>>();
+    assert_eq!(
+        events.last(),
+        Some(&StateEvent::TearDownFinished { state: "A" })
+    );
+}
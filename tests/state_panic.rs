@@ -0,0 +1,232 @@
+use may::go;
+use state_lock::{
+    CacheCapacity, PendingGroup, PoisonPolicy, SchedulingPolicy, State, StateEvent, StateLock,
+};
+
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+const STATE_FAMILY: &str = "StatePanic";
+
+static A_TEAR_UP: AtomicUsize = AtomicUsize::new(0);
+
+#[derive(State)]
+#[family(STATE_FAMILY)]
+struct A;
+
+impl Default for A {
+    fn default() -> Self {
+        A_TEAR_UP.fetch_add(1, Ordering::Relaxed);
+        A
+    }
+}
+
+#[derive(State, Default)]
+#[family(STATE_FAMILY)]
+struct B;
+
+#[derive(State)]
+#[family(STATE_FAMILY)]
+struct PanicTearUp;
+
+impl Default for PanicTearUp {
+    fn default() -> Self {
+        panic!("tear up panic")
+    }
+}
+
+struct PanicTearDown;
+
+impl PanicTearDown {
+    fn make() -> Box<dyn State> {
+        Box::new(PanicTearDown::tear_up())
+    }
+}
+
+impl State for PanicTearDown {
+    fn state_name() -> &'static str {
+        stringify!(PanicTearDown)
+    }
+    fn name(&self) -> &'static str {
+        Self::state_name()
+    }
+    fn family(&self) -> &'static str {
+        STATE_FAMILY
+    }
+    fn tear_up() -> Self {
+        PanicTearDown
+    }
+    fn tear_down(&mut self) {
+        panic!("tear down panic")
+    }
+}
+
+#[state_lock::linkme::distributed_slice(state_lock::STATE_REGISTRATION)]
+#[linkme(crate = state_lock::linkme)]
+static STATE_PANIC_TEAR_DOWN: state_lock::StateRegistration = state_lock::StateRegistration {
+    state_family: STATE_FAMILY,
+    state: stringify!(PanicTearDown),
+    tear_up_fn: PanicTearDown::make,
+};
+
+// a state that panics in the callbacks other than tear up and tear down
+struct PanicCallback;
+
+impl PanicCallback {
+    fn make() -> Box<dyn State> {
+        Box::new(PanicCallback::tear_up())
+    }
+}
+
+impl State for PanicCallback {
+    fn state_name() -> &'static str {
+        stringify!(PanicCallback)
+    }
+    fn name(&self) -> &'static str {
+        Self::state_name()
+    }
+    fn family(&self) -> &'static str {
+        STATE_FAMILY
+    }
+    fn tear_up() -> Self {
+        PanicCallback
+    }
+    fn size_hint(&self) -> usize {
+        panic!("size hint panic")
+    }
+    fn transition_into(&mut self, _next: &str) -> Option<Box<dyn State>> {
+        panic!("transition panic")
+    }
+}
+
+#[state_lock::linkme::distributed_slice(state_lock::STATE_REGISTRATION)]
+#[linkme(crate = state_lock::linkme)]
+static STATE_PANIC_CALLBACK: state_lock::StateRegistration = state_lock::StateRegistration {
+    state_family: STATE_FAMILY,
+    state: stringify!(PanicCallback),
+    tear_up_fn: PanicCallback::make,
+};
+
+struct PanicPolicy;
+
+impl SchedulingPolicy for PanicPolicy {
+    fn pick_next(&self, _last_state: Option<&str>, _groups: &[PendingGroup]) -> usize {
+        panic!("policy panic")
+    }
+}
+
+#[test]
+fn tear_up_panic() {
+    let state_lock = StateLock::new(STATE_FAMILY);
+    let err = state_lock.lock::<PanicTearUp>().unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        "PanicTearUp state tear up panicked: tear up panic"
+    );
+    assert!(state_lock.current_state().is_none());
+
+    // the lock is still usable
+    let b = state_lock.lock::<B>().unwrap();
+    assert_eq!(b.name(), "B");
+}
+
+#[test]
+fn tear_down_panic() {
+    let state_lock = StateLock::new(STATE_FAMILY);
+    let events = state_lock.subscribe();
+    drop(state_lock.lock::<PanicTearDown>().unwrap());
+    // the cached state is tear down for B, the panic is caught
+    let b = state_lock.lock::<B>().unwrap();
+    assert_eq!(b.name(), "B");
+    let tear_down = StateEvent::TearDownFinished {
+        state: "PanicTearDown",
+    };
+    assert!(events.try_iter().any(|e| e == tear_down));
+    drop(b);
+
+    // the lock still serves later requests
+    let state = state_lock.lock::<PanicTearDown>().unwrap();
+    assert_eq!(state.name(), "PanicTearDown");
+    drop(state);
+    assert_eq!(state_lock.lock::<B>().unwrap().name(), "B");
+}
+
+#[test]
+fn transition_panic() {
+    let state_lock = Arc::new(StateLock::new(STATE_FAMILY));
+    let events = state_lock.subscribe();
+    let state = state_lock.lock::<PanicCallback>().unwrap();
+
+    let waiters: Vec<_> = (0..2)
+        .map(|_| {
+            let state_lock = state_lock.clone();
+            go!(move || state_lock.lock::<B>().map(|s| s.name()))
+        })
+        .collect();
+    std::thread::sleep(Duration::from_millis(100));
+
+    // the panicked state is tear down, B is tear up as usual
+    drop(state);
+    for waiter in waiters {
+        assert_eq!(waiter.join().unwrap().unwrap(), "B");
+    }
+    let events = events.try_iter().collect::<Vec<_>>();
+    let tear_down = StateEvent::TearDownFinished {
+        state: "PanicCallback",
+    };
+    assert!(events.contains(&tear_down));
+    assert!(events.contains(&StateEvent::TearUpStarted { state: "B" }));
+}
+
+#[test]
+fn size_hint_panic() {
+    let state_lock = StateLock::new(STATE_FAMILY).with_state_cache(CacheCapacity::Weighted(10));
+    let events = state_lock.subscribe();
+    drop(state_lock.lock::<PanicCallback>().unwrap());
+
+    // the state is tear down instead of cached
+    let tear_down = StateEvent::TearDownFinished {
+        state: "PanicCallback",
+    };
+    assert!(events.try_iter().any(|e| e == tear_down));
+    assert!(state_lock.cached_states().is_empty());
+    assert_eq!(state_lock.lock::<B>().unwrap().name(), "B");
+}
+
+#[test]
+fn policy_panic() {
+    let state_lock = Arc::new(StateLock::new(STATE_FAMILY).with_scheduling_policy(PanicPolicy));
+    let state = state_lock.lock::<B>().unwrap();
+
+    let state_lock_1 = state_lock.clone();
+    let waiter_1 = go!(move || state_lock_1.lock::<PanicTearDown>().map(|s| s.name()));
+    std::thread::sleep(Duration::from_millis(50));
+    let state_lock_2 = state_lock.clone();
+    let waiter_2 = go!(move || state_lock_2.lock::<PanicCallback>().map(|s| s.name()));
+    std::thread::sleep(Duration::from_millis(50));
+
+    // the policy panics, the groups are served in the arrival order
+    drop(state);
+    assert_eq!(waiter_1.join().unwrap().unwrap(), "PanicTearDown");
+    assert_eq!(waiter_2.join().unwrap().unwrap(), "PanicCallback");
+}
+
+#[test]
+fn poison_policy() {
+    for (policy, tear_up) in [(PoisonPolicy::Reuse, 1), (PoisonPolicy::Rebuild, 2)] {
+        A_TEAR_UP.store(0, Ordering::Relaxed);
+        let state_lock = Arc::new(StateLock::new(STATE_FAMILY).with_poison_policy(policy));
+
+        let state_lock_1 = state_lock.clone();
+        let holder = go!(move || {
+            let _a = state_lock_1.lock::<A>().unwrap();
+            panic!("holder panic");
+        });
+        assert!(holder.join().is_err());
+
+        let a = state_lock.lock::<A>().unwrap();
+        assert_eq!(a.name(), "A");
+        assert_eq!(A_TEAR_UP.load(Ordering::Relaxed), tear_up);
+    }
+}
@@ -0,0 +1,46 @@
+use may::go;
+use state_lock::{LargestGroupFirst, State, StateLock};
+
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+const STATE_FAMILY: &str = "StatePolicy";
+
+#[derive(State, Default)]
+#[family(STATE_FAMILY)]
+struct A;
+
+#[derive(State, Default)]
+#[family(STATE_FAMILY)]
+struct B;
+
+#[derive(State, Default)]
+#[family(STATE_FAMILY)]
+struct C;
+
+#[test]
+fn largest_group_first() {
+    let state_lock =
+        Arc::new(StateLock::new(STATE_FAMILY).with_scheduling_policy(LargestGroupFirst));
+    let a = state_lock.lock::<A>().unwrap();
+    let order = Arc::new(Mutex::new(Vec::new()));
+
+    // B arrives first with one waiter, then C with three waiters
+    let mut waiters = Vec::new();
+    for name in ["B", "C", "C", "C"] {
+        let state_lock = state_lock.clone();
+        let order = order.clone();
+        waiters.push(go!(move || {
+            let state = state_lock.lock_by_state_name(name).unwrap();
+            order.lock().unwrap().push(state.name());
+            std::thread::sleep(Duration::from_millis(10));
+        }));
+        std::thread::sleep(Duration::from_millis(50));
+    }
+
+    drop(a);
+    for waiter in waiters {
+        waiter.join().unwrap();
+    }
+    assert_eq!(*order.lock().unwrap(), ["C", "C", "C", "B"]);
+}
@@ -0,0 +1,66 @@
+use may::go;
+use state_lock::{State, StateLock, StateLockSet};
+
+use std::io;
+use std::sync::Arc;
+
+const MODE_FAMILY: &str = "StateSetMode";
+const LAYOUT_FAMILY: &str = "StateSetLayout";
+
+#[derive(State, Default)]
+#[family(MODE_FAMILY)]
+struct A;
+
+#[derive(State, Default)]
+#[family(MODE_FAMILY)]
+struct B;
+
+#[derive(State, Default)]
+#[family(LAYOUT_FAMILY)]
+struct X;
+
+#[derive(State, Default)]
+#[family(LAYOUT_FAMILY)]
+struct Y;
+
+#[test]
+fn lock_all_opposite_combinations() {
+    let mode = Arc::new(StateLock::new(MODE_FAMILY));
+    let layout = Arc::new(StateLock::new(LAYOUT_FAMILY));
+
+    let mut jobs = Vec::new();
+    for i in 0..200 {
+        let mode = mode.clone();
+        let layout = layout.clone();
+        jobs.push(go!(move || {
+            // half of the jobs request in the opposite order and combination
+            let states = if i % 2 == 0 {
+                StateLockSet::lock_all(&[(&mode, "A"), (&layout, "X")]).unwrap()
+            } else {
+                StateLockSet::lock_all(&[(&layout, "Y"), (&mode, "B")]).unwrap()
+            };
+            let names = states.iter().map(|s| s.name()).collect::<Vec<_>>();
+            if i % 2 == 0 {
+                assert_eq!(names, ["A", "X"]);
+            } else {
+                assert_eq!(names, ["Y", "B"]);
+            }
+        }));
+    }
+    for job in jobs {
+        job.join().unwrap();
+    }
+}
+
+#[test]
+fn lock_all_same_lock() {
+    let mode = StateLock::new(MODE_FAMILY);
+
+    let states = StateLockSet::lock_all(&[(&mode, "A"), (&mode, "A")]).unwrap();
+    assert_eq!(states.len(), 2);
+    drop(states);
+
+    let err = StateLockSet::lock_all(&[(&mode, "A"), (&mode, "B")]).unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+    assert!(mode.current_state().is_none());
+}
@@ -0,0 +1,75 @@
+use may::go;
+use state_lock::{State, StateEvent, StateLock};
+
+use std::io;
+use std::sync::Arc;
+use std::time::Duration;
+
+const STATE_FAMILY: &str = "StateShutdown";
+
+#[derive(State, Default)]
+#[family(STATE_FAMILY)]
+struct A;
+
+#[derive(State, Default)]
+#[family(STATE_FAMILY)]
+struct B;
+
+fn tear_down_event(state: &'static str) -> StateEvent {
+    StateEvent::TearDownFinished { state }
+}
+
+#[test]
+fn shutdown() {
+    let state_lock = Arc::new(StateLock::new(STATE_FAMILY));
+    let events = state_lock.subscribe();
+
+    let state_lock_1 = state_lock.clone();
+    let holder = go!(move || {
+        let _a = state_lock_1.lock::<A>().unwrap();
+        std::thread::sleep(Duration::from_millis(200));
+    });
+    std::thread::sleep(Duration::from_millis(50));
+    let state_lock_1 = state_lock.clone();
+    let waiter = go!(move || state_lock_1.lock::<B>().unwrap_err());
+    std::thread::sleep(Duration::from_millis(50));
+
+    // wait for the holder to release the state
+    state_lock.shutdown(Duration::from_secs(1)).unwrap();
+    holder.join().unwrap();
+    let err = waiter.join().unwrap();
+    assert_eq!(err.to_string(), "StateShutdown state lock is shutting down");
+    assert!(events.try_iter().any(|e| e == tear_down_event("A")));
+
+    // new acquisitions are rejected
+    assert!(state_lock.lock::<A>().is_err());
+    assert!(state_lock.try_lock::<B>().is_err());
+    assert!(state_lock.current_state().is_none());
+}
+
+#[test]
+fn shutdown_timeout() {
+    let state_lock = StateLock::new(STATE_FAMILY);
+    let events = state_lock.subscribe();
+
+    let a = state_lock.lock::<A>().unwrap();
+    let err = state_lock.shutdown(Duration::from_millis(50)).unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::TimedOut);
+    assert!(events.try_iter().all(|e| e != tear_down_event("A")));
+
+    // the state is tear down once released
+    drop(a);
+    assert!(events.try_iter().any(|e| e == tear_down_event("A")));
+    assert!(state_lock.cached_states().is_empty());
+}
+
+#[test]
+fn drop_tear_down() {
+    let state_lock = StateLock::new(STATE_FAMILY);
+    let events = state_lock.subscribe();
+
+    drop(state_lock.lock::<A>().unwrap());
+    assert_eq!(state_lock.cached_states(), ["A"]);
+    drop(state_lock);
+    assert!(events.iter().any(|e| e == tear_down_event("A")));
+}
@@ -0,0 +1,59 @@
+use may::go;
+use state_lock::{State, StateEvent, StateLock};
+
+use std::sync::Arc;
+use std::time::Duration;
+
+const STATE_FAMILY: &str = "StateStats";
+
+#[derive(State, Default)]
+#[family(STATE_FAMILY)]
+struct A;
+
+#[derive(State, Default)]
+#[family(STATE_FAMILY)]
+struct B;
+
+#[test]
+fn state_stats() {
+    let state_lock = Arc::new(StateLock::new(STATE_FAMILY));
+
+    let a1 = state_lock.lock::<A>().unwrap();
+    let a2 = state_lock.lock::<A>().unwrap();
+    drop((a1, a2));
+
+    // A is tear down to make room for B
+    let b = state_lock.lock::<B>().unwrap();
+    let events = state_lock.subscribe();
+    let state_lock_1 = state_lock.clone();
+    let waiter = go!(move || drop(state_lock_1.lock::<A>().unwrap()));
+    // the wait time starts once the waiter is queued
+    while !matches!(
+        events.recv().unwrap(),
+        StateEvent::WaiterQueued { state: "A" }
+    ) {}
+    std::thread::sleep(Duration::from_millis(100));
+    drop(b);
+    waiter.join().unwrap();
+
+    // the cached A is reused
+    drop(state_lock.lock::<A>().unwrap());
+
+    let stats = state_lock.stats();
+    let a = stats.get("A").unwrap();
+    assert_eq!(a.acquisitions, 4);
+    assert_eq!(a.transitions_in, 3);
+    assert_eq!(a.transitions_out, 3);
+    assert_eq!(a.cache_reuses, 1);
+    assert_eq!(a.tear_up_time.count(), 2);
+    assert_eq!(a.tear_down_time.count(), 1);
+    assert_eq!(a.wait_time.count(), 1);
+    assert!(a.wait_time.max() >= Duration::from_millis(100));
+    assert!(a.wait_time.percentile(0.5) >= Duration::from_millis(100));
+
+    let b = stats.get("B").unwrap();
+    assert_eq!(b.acquisitions, 1);
+    assert_eq!(b.transitions_out, 1);
+    assert_eq!(b.tear_down_time.count(), 1);
+    assert!(b.active_time >= Duration::from_millis(100));
+}
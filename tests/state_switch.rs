@@ -0,0 +1,62 @@
+use may::go;
+use state_lock::{State, StateLock};
+
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+const STATE_FAMILY: &str = "StateSwitch";
+
+static A_TEAR_UP: AtomicUsize = AtomicUsize::new(0);
+
+#[derive(State)]
+#[family(STATE_FAMILY)]
+struct A;
+
+impl Default for A {
+    fn default() -> Self {
+        A_TEAR_UP.fetch_add(1, Ordering::Relaxed);
+        A
+    }
+}
+
+#[derive(State, Default)]
+#[family(STATE_FAMILY)]
+struct B;
+
+#[test]
+fn switch_state() {
+    let state_lock = Arc::new(StateLock::new(STATE_FAMILY));
+
+    // switch to the same state just keep it
+    let a = state_lock.lock::<A>().unwrap();
+    let a = a.switch_to::<A>().unwrap();
+    assert_eq!(A_TEAR_UP.load(Ordering::Relaxed), 1);
+
+    // the state is released after all guards switched away
+    let state_lock_1 = state_lock.clone();
+    let other = go!(move || {
+        let a = state_lock_1.lock::<A>().unwrap();
+        std::thread::sleep(Duration::from_millis(100));
+        let b = a.switch_to::<B>().unwrap();
+        assert_eq!(b.name(), "B");
+    });
+    std::thread::sleep(Duration::from_millis(50));
+    let b = a.switch_to::<B>().unwrap();
+    assert_eq!(b.name(), "B");
+    other.join().unwrap();
+
+    // join the group that is already waiting for A
+    let state_lock_1 = state_lock.clone();
+    let waiter = go!(move || drop(state_lock_1.lock::<A>().unwrap()));
+    std::thread::sleep(Duration::from_millis(50));
+    let raw = b.switch_to::<A>().unwrap().switch_to::<B>();
+    assert_eq!(raw.unwrap().name(), "B");
+    waiter.join().unwrap();
+
+    let raw = state_lock.lock_by_state_name("A").unwrap();
+    assert!(raw.switch_to("C").is_err());
+    // the state is released when failed to switch
+    let b = state_lock.try_lock::<B>().unwrap();
+    assert_eq!(b.name(), "B");
+}
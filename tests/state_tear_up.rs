@@ -0,0 +1,108 @@
+use may::go;
+use state_lock::{State, StateLock};
+
+use std::io;
+use std::sync::Arc;
+use std::time::Duration;
+
+const STATE_FAMILY: &str = "StateTearUp";
+
+struct Broken;
+
+impl Broken {
+    fn make() -> io::Result<Box<dyn State>> {
+        Ok(Box::new(Broken::try_tear_up()?))
+    }
+}
+
+impl State for Broken {
+    fn state_name() -> &'static str {
+        stringify!(Broken)
+    }
+    fn name(&self) -> &'static str {
+        Self::state_name()
+    }
+    fn family(&self) -> &'static str {
+        STATE_FAMILY
+    }
+    fn tear_up() -> Self {
+        Broken
+    }
+    fn try_tear_up() -> io::Result<Self> {
+        Err(io::Error::other("broken state"))
+    }
+}
+
+#[state_lock::linkme::distributed_slice(state_lock::FALLIBLE_STATE_REGISTRATION)]
+#[linkme(crate = state_lock::linkme)]
+static STATE_BROKEN: state_lock::FallibleStateRegistration =
+    state_lock::FallibleStateRegistration {
+        state_family: STATE_FAMILY,
+        state: stringify!(Broken),
+        try_tear_up_fn: Broken::make,
+    };
+
+#[derive(State, Default)]
+#[family(STATE_FAMILY)]
+struct A;
+
+#[derive(State, Default)]
+#[family(STATE_FAMILY)]
+struct B;
+
+fn derived_broken() -> io::Result<DerivedBroken> {
+    Err(io::Error::other("derived broken state"))
+}
+
+#[derive(State, Default)]
+#[family(STATE_FAMILY)]
+#[state_lock(try_tear_up = derived_broken)]
+struct DerivedBroken;
+
+#[test]
+fn tear_up_error_without_waiting() {
+    let state_lock = StateLock::new(STATE_FAMILY);
+    let err = state_lock.lock::<Broken>().unwrap_err();
+    assert_eq!(err.to_string(), "broken state");
+    assert!(state_lock.current_state().is_none());
+
+    // the lock is still usable
+    let a = state_lock.lock::<A>().unwrap();
+    assert_eq!(a.name(), "A");
+}
+
+#[test]
+fn tear_up_error_to_all_waiters() {
+    let state_lock = Arc::new(StateLock::new(STATE_FAMILY));
+    let a = state_lock.lock::<A>().unwrap();
+
+    let broken_waiters: Vec<_> = (0..3)
+        .map(|_| {
+            let state_lock = state_lock.clone();
+            go!(move || state_lock.lock::<Broken>().map(|_| ()))
+        })
+        .collect();
+    std::thread::sleep(Duration::from_millis(100));
+
+    let state_lock_b = state_lock.clone();
+    let b_waiter = go!(move || state_lock_b.lock::<B>().map(|s| s.name()));
+    std::thread::sleep(Duration::from_millis(100));
+
+    // release A, the broken group fails and the lock moves on to B
+    drop(a);
+    for waiter in broken_waiters {
+        let err = waiter.join().unwrap().unwrap_err();
+        assert_eq!(err.to_string(), "broken state");
+    }
+    assert_eq!(b_waiter.join().unwrap().unwrap(), "B");
+}
+
+#[test]
+fn derived_try_tear_up() {
+    let state_lock = StateLock::new(STATE_FAMILY);
+    let err = state_lock.lock::<DerivedBroken>().unwrap_err();
+    assert_eq!(err.to_string(), "derived broken state");
+
+    let b = state_lock.lock::<B>().unwrap();
+    assert_eq!(b.name(), "B");
+}
@@ -0,0 +1,53 @@
+use may::go;
+use state_lock::{State, StateLock};
+
+use std::io;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+const STATE_FAMILY: &str = "StateTimeout";
+
+static B_TEAR_UP: AtomicUsize = AtomicUsize::new(0);
+
+#[derive(State, Default)]
+#[family(STATE_FAMILY)]
+struct A;
+
+#[derive(State)]
+#[family(STATE_FAMILY)]
+struct B;
+
+impl Default for B {
+    fn default() -> Self {
+        B_TEAR_UP.fetch_add(1, Ordering::Relaxed);
+        B
+    }
+}
+
+#[test]
+fn lock_timeout() {
+    let state_lock = Arc::new(StateLock::new(STATE_FAMILY));
+    let a = state_lock.lock::<A>().unwrap();
+
+    let state_lock_1 = state_lock.clone();
+    let waiter = go!(move || {
+        let err = state_lock_1
+            .lock_timeout::<B>(Duration::from_millis(100))
+            .unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::TimedOut);
+    });
+    waiter.join().unwrap();
+
+    // the timed out waiter is removed, B would not be tear up for nobody
+    drop(a);
+    assert!(state_lock.current_state().is_none());
+    assert_eq!(B_TEAR_UP.load(Ordering::Relaxed), 0);
+
+    // no contention, the state is ready immediately
+    let b = state_lock
+        .lock_timeout::<B>(Duration::from_millis(100))
+        .unwrap();
+    assert_eq!(b.name(), "B");
+    assert_eq!(B_TEAR_UP.load(Ordering::Relaxed), 1);
+}
@@ -0,0 +1,186 @@
+#![cfg(feature = "tracing")]
+
+use may::go;
+use state_lock::{State, StateLock};
+use tracing::field::{Field, Visit};
+use tracing::span::{Attributes, Id, Record};
+use tracing::{Event, Metadata, Subscriber};
+
+use std::collections::HashMap;
+use std::fmt;
+use std::future::Future;
+use std::pin::pin;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Wake, Waker};
+use std::time::Duration;
+
+const STATE_FAMILY: &str = "StateTracing";
+
+#[derive(State, Default)]
+#[family(STATE_FAMILY)]
+struct A;
+
+#[derive(State, Default)]
+#[family(STATE_FAMILY)]
+struct B;
+
+#[derive(Default)]
+struct SpanInfo {
+    name: &'static str,
+    entered: usize,
+    fields: HashMap<&'static str, String>,
+    parent: Option<u64>,
+    closed: bool,
+}
+
+impl Visit for SpanInfo {
+    fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+        self.fields.insert(field.name(), format!("{value:?}"));
+    }
+}
+
+// collect the spans of the state lock, the spans of all threads are collected
+#[derive(Default)]
+struct SpanCollector {
+    next_id: AtomicU64,
+    spans: Mutex<HashMap<u64, SpanInfo>>,
+}
+
+impl SpanCollector {
+    fn spans(&self, name: &str) -> Vec<(usize, HashMap<&'static str, String>)> {
+        let spans = self.spans.lock().unwrap();
+        let spans = spans.values().filter(|s| s.name == name);
+        spans.map(|s| (s.entered, s.fields.clone())).collect()
+    }
+
+    // the parent span names of the wait spans
+    fn wait_span_parents(&self) -> Vec<&'static str> {
+        let spans = self.spans.lock().unwrap();
+        let waits = spans.values().filter(|s| s.name == "state_lock.wait");
+        let parents = waits.map(|s| {
+            // the wait span is never entered, it's closed once the wait is done
+            assert_eq!(s.entered, 0);
+            assert!(s.closed);
+            let parent = s.parent.expect("no parent span");
+            spans[&parent].name
+        });
+        let mut parents = parents.collect::<Vec<_>>();
+        parents.sort();
+        parents
+    }
+}
+
+impl Subscriber for &'static SpanCollector {
+    fn enabled(&self, metadata: &Metadata<'_>) -> bool {
+        metadata.name().starts_with("state_lock.")
+    }
+
+    fn new_span(&self, span: &Attributes<'_>) -> Id {
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed) + 1;
+        let mut info = SpanInfo {
+            name: span.metadata().name(),
+            parent: span.parent().map(|id| id.into_u64()),
+            ..Default::default()
+        };
+        span.record(&mut info);
+        self.spans.lock().unwrap().insert(id, info);
+        Id::from_u64(id)
+    }
+
+    fn record(&self, span: &Id, values: &Record<'_>) {
+        if let Some(info) = self.spans.lock().unwrap().get_mut(&span.into_u64()) {
+            values.record(info);
+        }
+    }
+
+    fn record_follows_from(&self, _span: &Id, _follows: &Id) {}
+
+    fn event(&self, _event: &Event<'_>) {}
+
+    fn enter(&self, span: &Id) {
+        if let Some(info) = self.spans.lock().unwrap().get_mut(&span.into_u64()) {
+            info.entered += 1;
+        }
+    }
+
+    fn exit(&self, _span: &Id) {}
+
+    fn try_close(&self, span: Id) -> bool {
+        if let Some(info) = self.spans.lock().unwrap().get_mut(&span.into_u64()) {
+            info.closed = true;
+        }
+        true
+    }
+}
+
+struct NoopWaker;
+
+impl Wake for NoopWaker {
+    fn wake(self: Arc<Self>) {}
+}
+
+#[test]
+fn tracing_spans() {
+    let collector: &'static SpanCollector = Box::leak(Box::default());
+    tracing::subscriber::set_global_default(collector).unwrap();
+
+    let state_lock = Arc::new(StateLock::new(STATE_FAMILY));
+    let a = state_lock.lock::<A>().unwrap();
+    drop(state_lock.try_lock::<A>().unwrap());
+
+    // B waits for A
+    let state_lock_1 = state_lock.clone();
+    let waiter = go!(move || drop(state_lock_1.lock::<B>().unwrap()));
+    std::thread::sleep(Duration::from_millis(100));
+    drop(a);
+    waiter.join().unwrap();
+
+    // switch to A waits for the other guard of B, then lock A exclusively
+    let b = state_lock.lock::<B>().unwrap();
+    let b_1 = state_lock.lock_owned::<B>().unwrap();
+    let holder = go!(move || {
+        std::thread::sleep(Duration::from_millis(100));
+        drop(b_1);
+    });
+    drop(b.switch_to::<A>().unwrap());
+    holder.join().unwrap();
+    drop(state_lock.lock_exclusive::<A>().unwrap());
+
+    // the async lock is ready on the first poll
+    let waker = Waker::from(Arc::new(NoopWaker));
+    let mut future = pin!(state_lock.lock_async::<B>());
+    let ready = future.as_mut().poll(&mut Context::from_waker(&waker));
+    assert!(ready.is_ready());
+
+    let acquire = collector.spans("state_lock.acquire");
+    // lock A, lock B with waiting, lock B twice and lock A exclusively
+    assert_eq!(acquire.len(), 5);
+    assert!(acquire.iter().all(|(entered, _)| *entered == 1));
+    let waited = acquire.iter().filter(|(_, f)| f["waited"] == "true");
+    let waited = waited.collect::<Vec<_>>();
+    assert_eq!(waited.len(), 1);
+    assert_eq!(waited[0].1["state"], "B");
+    assert!(waited[0].1.contains_key("wait_us"));
+    let exclusive = acquire.iter().filter(|(_, f)| f["exclusive"] == "true");
+    assert_eq!(exclusive.count(), 1);
+
+    for name in [
+        "state_lock.try_lock",
+        "state_lock.switch",
+        "state_lock.acquire_async",
+    ] {
+        let spans = collector.spans(name);
+        assert_eq!(spans.len(), 1, "{name}");
+        assert_eq!(spans[0].0, 1, "{name}");
+    }
+    assert_eq!(collector.spans("state_lock.switch")[0].1["to"], "A");
+    // the waits of B and the switch
+    let wait = collector.wait_span_parents();
+    assert_eq!(wait, ["state_lock.acquire", "state_lock.switch"]);
+
+    let tear_up = collector.spans("state_lock.tear_up");
+    assert!(!tear_up.is_empty());
+    assert!(tear_up.iter().all(|(entered, _)| *entered == 1));
+    assert!(!collector.spans("state_lock.tear_down").is_empty());
+}
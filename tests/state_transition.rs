@@ -0,0 +1,124 @@
+use state_lock::{State, StateLock};
+
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+const STATE_FAMILY: &str = "StateTransition";
+
+static DEVICE_OPEN: AtomicUsize = AtomicUsize::new(0);
+static DEVICE_CLOSE: AtomicUsize = AtomicUsize::new(0);
+
+// an expensive resource that could be reconfigured
+struct Device {
+    mode: &'static str,
+}
+
+impl Device {
+    fn open(mode: &'static str) -> Self {
+        DEVICE_OPEN.fetch_add(1, Ordering::Relaxed);
+        Device { mode }
+    }
+}
+
+impl Drop for Device {
+    fn drop(&mut self) {
+        DEVICE_CLOSE.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+struct Read {
+    device: Option<Device>,
+}
+
+impl Read {
+    fn make() -> Box<dyn State> {
+        Box::new(Read::tear_up())
+    }
+}
+
+impl State for Read {
+    fn state_name() -> &'static str {
+        stringify!(Read)
+    }
+    fn name(&self) -> &'static str {
+        Self::state_name()
+    }
+    fn family(&self) -> &'static str {
+        STATE_FAMILY
+    }
+    fn tear_up() -> Self {
+        let device = Some(Device::open("read"));
+        Read { device }
+    }
+    fn transition_into(&mut self, next: &str) -> Option<Box<dyn State>> {
+        if next != Write::state_name() {
+            return None;
+        }
+        let mut device = self.device.take()?;
+        device.mode = "write";
+        Some(Box::new(Write { device }))
+    }
+}
+
+#[state_lock::linkme::distributed_slice(state_lock::STATE_REGISTRATION)]
+#[linkme(crate = state_lock::linkme)]
+static STATE_READ: state_lock::StateRegistration = state_lock::StateRegistration {
+    state_family: STATE_FAMILY,
+    state: stringify!(Read),
+    tear_up_fn: Read::make,
+};
+
+struct Write {
+    device: Device,
+}
+
+impl Write {
+    fn make() -> Box<dyn State> {
+        Box::new(Write::tear_up())
+    }
+}
+
+impl State for Write {
+    fn state_name() -> &'static str {
+        stringify!(Write)
+    }
+    fn name(&self) -> &'static str {
+        Self::state_name()
+    }
+    fn family(&self) -> &'static str {
+        STATE_FAMILY
+    }
+    fn tear_up() -> Self {
+        let device = Device::open("write");
+        Write { device }
+    }
+}
+
+#[state_lock::linkme::distributed_slice(state_lock::STATE_REGISTRATION)]
+#[linkme(crate = state_lock::linkme)]
+static STATE_WRITE: state_lock::StateRegistration = state_lock::StateRegistration {
+    state_family: STATE_FAMILY,
+    state: stringify!(Write),
+    tear_up_fn: Write::make,
+};
+
+#[test]
+fn transition_in_place() {
+    let state_lock = StateLock::new(STATE_FAMILY);
+
+    let read = state_lock.lock::<Read>().unwrap();
+    assert_eq!(read.device.as_ref().unwrap().mode, "read");
+    drop(read);
+
+    // the device is reconfigured instead of reopened
+    let write = state_lock.lock::<Write>().unwrap();
+    assert_eq!(write.device.mode, "write");
+    assert_eq!(DEVICE_OPEN.load(Ordering::Relaxed), 1);
+    assert_eq!(DEVICE_CLOSE.load(Ordering::Relaxed), 0);
+    drop(write);
+
+    // no transition hook from Write, fall back to tear down and tear up
+    let read = state_lock.lock::<Read>().unwrap();
+    assert_eq!(read.device.as_ref().unwrap().mode, "read");
+    assert_eq!(DEVICE_OPEN.load(Ordering::Relaxed), 2);
+    assert_eq!(DEVICE_CLOSE.load(Ordering::Relaxed), 1);
+}
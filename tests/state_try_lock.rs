@@ -0,0 +1,55 @@
+use state_lock::{State, StateLock};
+
+use std::io;
+
+const STATE_FAMILY: &str = "StateTryLock";
+
+#[derive(State, Default)]
+#[family(STATE_FAMILY)]
+struct A;
+
+#[derive(State, Default)]
+#[family(STATE_FAMILY)]
+struct B;
+
+#[test]
+fn try_lock() {
+    let state_lock = StateLock::new(STATE_FAMILY);
+
+    // no active state, tear up directly
+    let a = state_lock.try_lock::<A>().unwrap();
+    // same state, join the active state
+    let a1 = state_lock.try_lock::<A>().unwrap();
+    assert_eq!(a1.name(), "A");
+
+    // different state, never block
+    let err = state_lock.try_lock::<B>().unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::WouldBlock);
+
+    drop(a);
+    drop(a1);
+    let b = state_lock.try_lock::<B>().unwrap();
+    assert_eq!(b.name(), "B");
+}
+
+#[test]
+fn lock_if_current() {
+    let state_lock = StateLock::new(STATE_FAMILY);
+
+    // nothing is active, there is nothing to attach to
+    let err = state_lock.lock_if_current("A").unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::WouldBlock);
+    assert!(state_lock.current_state().is_none());
+
+    let a = state_lock.lock::<A>().unwrap();
+    let state = state_lock.lock_if_current("A").unwrap();
+    assert_eq!(state.name(), "A");
+
+    let err = state_lock.lock_if_current("B").unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::WouldBlock);
+
+    // unknown state is still an error
+    let err = state_lock.lock_if_current("X").unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::Other);
+    drop(a);
+}